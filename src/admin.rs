@@ -0,0 +1,481 @@
+use std::collections::HashSet;
+
+use askama::Template;
+use axum::{
+    Form,
+    extract::Path,
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    Access, BroadcastChannel, Config, Identifier, NotifierConfig, Storage, Templates, Visibility,
+    key_hint, mermaid::Mermaid,
+};
+
+type Failed = (StatusCode, Html<String>);
+
+const VISIBILITIES: [&str; 3] = ["public", "unlisted", "private"];
+
+#[derive(Template)]
+#[template(path = "admin_login.html")]
+struct LoginPage<'a> {
+    // whether `admin_token` is set
+    enabled: bool,
+    error: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct DashboardPage<'a> {
+    config: &'static Config,
+    // id, options and comma separated users, by id
+    channels: Vec<(u64, &'static BroadcastChannel, String)>,
+    // hints of each api key
+    keys: Vec<String>,
+    error: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct UserForm {
+    name: String,
+    uuid: String,
+    // empty if not linked
+    #[serde(default)]
+    discord: String,
+    #[serde(default)]
+    visibility: Visibility,
+}
+
+#[derive(Deserialize)]
+pub struct NewChannelForm {
+    channel: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChannelForm {
+    #[serde(flatten)]
+    templates: Templates,
+    // comma separated
+    #[serde(default)]
+    users: String,
+    // checkboxes are only sent when checked
+    embed: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct KeyForm {
+    // the key when adding, its hint when removing
+    key: String,
+}
+
+pub async fn page(access: Access) -> Html<String> {
+    if access.admin {
+        Html(dashboard(None))
+    } else {
+        Html(login(None))
+    }
+}
+
+pub async fn log_in(headers: HeaderMap, Form(form): Form<LoginForm>) -> Result<Response, Failed> {
+    if !Access::is_admin_token(&form.token) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Html(login(Some("Invalid admin token"))),
+        ));
+    }
+    // would end the cookie early or be split into several
+    if !form
+        .token
+        .bytes()
+        .all(|byte| byte.is_ascii_graphic() && !b"\",;\\".contains(&byte))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Html(login(Some(
+                "The admin token cannot be stored in a cookie, use one without spaces, quotes, commas, semicolons or backslashes",
+            ))),
+        ));
+    }
+
+    // served over https by a reverse proxy, the cookie is then never sent in the clear
+    let secure = headers
+        .get("x-forwarded-proto")
+        .is_some_and(|proto| proto.as_bytes().eq_ignore_ascii_case(b"https"));
+
+    // strict same site cookies are not sent with forms posted from other sites
+    Ok((
+        [(
+            header::SET_COOKIE,
+            format!(
+                "token={}; HttpOnly; SameSite=Strict; Path=/{}",
+                form.token,
+                if secure { "; Secure" } else { "" }
+            ),
+        )],
+        Redirect::to("/admin"),
+    )
+        .into_response())
+}
+
+pub async fn log_out() -> impl IntoResponse {
+    (
+        [(
+            header::SET_COOKIE,
+            "token=; HttpOnly; SameSite=Strict; Path=/; Max-Age=0",
+        )],
+        Redirect::to("/admin"),
+    )
+}
+
+pub async fn add_user(access: Access, Form(form): Form<UserForm>) -> Result<Redirect, Failed> {
+    authorize(&access)?;
+    let user = form.identifier()?;
+    edit(&access, move |config| {
+        if config.users.iter().any(|entry| entry.name == user.name) {
+            return Err(format!("`{}` is already tracked", user.name));
+        }
+        config.users.push(user);
+        Ok(())
+    })
+    .await
+}
+
+pub async fn update_user(
+    access: Access,
+    Path((name,)): Path<(String,)>,
+    Form(form): Form<UserForm>,
+) -> Result<Redirect, Failed> {
+    authorize(&access)?;
+    let user = form.identifier()?;
+    let renamed = (user.name != name).then(|| user.name.clone());
+
+    // held until the sessions are moved, so a fetch cannot record the new name first
+    let mut storage = Storage::get().lock().await;
+    if let Some(renamed) = &renamed
+        && storage.recorded(renamed)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Html(dashboard(Some(&format!(
+                "sessions of a removed user are still recorded as `{renamed}`, pick another name"
+            )))),
+        ));
+    }
+
+    let redirect = edit(&access, {
+        let (name, renamed) = (name.clone(), renamed.clone());
+        move |config| {
+            let entry = config
+                .users
+                .iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or(format!("`{name}` is not tracked"))?;
+            *entry = user;
+
+            if let Some(renamed) = &renamed {
+                for channel in config.broadcast_channels.values_mut() {
+                    if channel.users.remove(&name) {
+                        channel.users.insert(renamed.clone());
+                    }
+                }
+                for notifier in config.notifiers.iter_mut() {
+                    if notifier.users_mut().remove(&name) {
+                        notifier.users_mut().insert(renamed.clone());
+                    }
+                }
+                if let Some(limits) = config.limits.remove(&name) {
+                    config.limits.insert(renamed.clone(), limits);
+                }
+            }
+            Ok(())
+        }
+    })
+    .await?;
+
+    if let Some(renamed) = renamed {
+        storage.rename(&name, &renamed);
+        storage.publish();
+        Mermaid::update();
+        tracing::info!(from = name, to = renamed, "Renamed user");
+    }
+    Ok(redirect)
+}
+
+pub async fn remove_user(
+    access: Access,
+    Path((name,)): Path<(String,)>,
+) -> Result<Redirect, Failed> {
+    let redirect = edit(&access, {
+        let name = name.clone();
+        move |config| {
+            let before = config.users.len();
+            config.users.retain(|entry| entry.name != name);
+            if config.users.len() == before {
+                return Err(format!("`{name}` is not tracked"));
+            }
+
+            // channels naming an untracked user could not be saved anymore
+            for channel in config.broadcast_channels.values_mut() {
+                channel.users.remove(&name);
+            }
+            for notifier in config.notifiers.iter_mut() {
+                notifier.users_mut().remove(&name);
+            }
+            config.limits.remove(&name);
+            Ok(())
+        }
+    })
+    .await?;
+
+    // recorded sessions are kept, in case the user is added back
+    Storage::get().lock().await.unshare(&name);
+    Ok(redirect)
+}
+
+pub async fn add_channel(
+    access: Access,
+    Form(form): Form<NewChannelForm>,
+) -> Result<Redirect, Failed> {
+    authorize(&access)?;
+    let channel = parse_channel(&form.channel)?;
+    edit(&access, move |config| {
+        if config.broadcast_channels.contains_key(&channel) {
+            return Err(format!("channel {channel} already exists"));
+        }
+        config.broadcast_channels.insert(
+            channel,
+            BroadcastChannel {
+                templates: Templates::default(),
+                users: HashSet::new(),
+                embed: false,
+                colours: Default::default(),
+                status: false,
+                digest: None,
+            },
+        );
+        Ok(())
+    })
+    .await
+}
+
+pub async fn update_channel(
+    access: Access,
+    Path((channel,)): Path<(u64,)>,
+    Form(form): Form<ChannelForm>,
+) -> Result<Redirect, Failed> {
+    edit(&access, move |config| {
+        let users = form
+            .users
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect::<HashSet<_>>();
+        if let Some(unknown) = users
+            .iter()
+            .find(|name| !config.users.iter().any(|entry| &entry.name == *name))
+        {
+            return Err(format!("`{unknown}` is not tracked"));
+        }
+
+        let options = config
+            .broadcast_channels
+            .get_mut(&channel)
+            .ok_or(format!("channel {channel} does not exist"))?;
+        options.templates = form.templates;
+        options.users = users;
+        options.embed = form.embed.is_some();
+        options.status = form.status.is_some();
+        Ok(())
+    })
+    .await
+}
+
+pub async fn remove_channel(
+    access: Access,
+    Path((channel,)): Path<(u64,)>,
+) -> Result<Redirect, Failed> {
+    edit(&access, move |config| {
+        config
+            .broadcast_channels
+            .remove(&channel)
+            .map(|_| ())
+            .ok_or(format!("channel {channel} does not exist"))
+    })
+    .await
+}
+
+pub async fn add_key(access: Access, Form(form): Form<KeyForm>) -> Result<Redirect, Failed> {
+    edit(&access, move |config| {
+        if !config.keys.insert(form.key.trim().to_string()) {
+            return Err("this key is already in use".to_string());
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub async fn remove_key(access: Access, Form(form): Form<KeyForm>) -> Result<Redirect, Failed> {
+    edit(&access, move |config| {
+        let matching = config
+            .keys
+            .iter()
+            .filter(|key| key_hint(key) == form.key)
+            .cloned()
+            .collect::<Vec<_>>();
+        match matching.as_slice() {
+            [key] => {
+                config.keys.remove(key);
+                Ok(())
+            }
+            [] => Err(format!("no key ends in `{}`", form.key)),
+            _ => Err(format!(
+                "several keys end in `{}`, remove it from master.json instead",
+                form.key
+            )),
+        }
+    })
+    .await
+}
+
+// saves the edited config, or renders why it was rejected
+async fn edit(
+    access: &Access,
+    edit: impl FnOnce(&mut Config) -> Result<(), String> + Send + 'static,
+) -> Result<Redirect, Failed> {
+    authorize(access)?;
+    Config::update(edit)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, Html(dashboard(Some(&e)))))?;
+    Ok(Redirect::to("/admin"))
+}
+
+// called before anything that can render the dashboard, which shows the whole config
+fn authorize(access: &Access) -> Result<(), Failed> {
+    access
+        .admin()
+        .map_err(|status| (status, Html(login(Some("Log in to make changes")))))
+}
+
+fn parse_channel(channel: &str) -> Result<u64, Failed> {
+    channel.trim().parse().map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            Html(dashboard(Some(&format!("`{channel}` is not a channel id")))),
+        )
+    })
+}
+
+impl UserForm {
+    fn identifier(self) -> Result<Identifier, Failed> {
+        let discord = match self.discord.trim() {
+            "" => None,
+            discord => Some(discord.parse().map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Html(dashboard(Some(&format!(
+                        "`{discord}` is not a discord user id"
+                    )))),
+                )
+            })?),
+        };
+
+        Ok(Identifier {
+            name: self.name.trim().to_string(),
+            // accepts the dashed form shown by most uuid lookups
+            uuid: self.uuid.trim().replace('-', "").to_lowercase(),
+            discord,
+            visibility: self.visibility,
+        })
+    }
+}
+
+impl NotifierConfig {
+    fn users_mut(&mut self) -> &mut HashSet<String> {
+        match self {
+            Self::DiscordWebhook { users, .. }
+            | Self::Webhook { users, .. }
+            | Self::Ntfy { users, .. }
+            | Self::Stdout { users, .. } => users,
+        }
+    }
+}
+
+impl Storage {
+    /// Whether anything is recorded for `user`, a user that was removed keeps their sessions.
+    pub fn recorded(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+            || self.modes.contains_key(user)
+            || self.alerts.contains_key(user)
+            || self.subscriptions.contains_key(user)
+    }
+
+    /// Moves everything recorded for `from` over to `to`, which must have nothing recorded.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some(records) = self.users.remove(from) {
+            self.users.insert(to.to_string(), records);
+        }
+        if let Some(mode) = self.modes.remove(from) {
+            self.modes.insert(to.to_string(), mode);
+        }
+        if let Some(alerts) = self.alerts.remove(from) {
+            self.alerts.insert(to.to_string(), alerts);
+        }
+        if let Some(subscribers) = self.subscriptions.remove(from) {
+            self.subscriptions.insert(to.to_string(), subscribers);
+        }
+        self.shares
+            .values_mut()
+            .filter(|user| *user == from)
+            .for_each(|user| *user = to.to_string());
+        self.save();
+    }
+}
+
+fn login(error: Option<&str>) -> String {
+    LoginPage {
+        enabled: Config::get().admin_token.is_some(),
+        error,
+    }
+    .render()
+    .unwrap()
+}
+
+fn dashboard(error: Option<&str>) -> String {
+    let config = Config::get();
+
+    let mut channels = config
+        .broadcast_channels
+        .iter()
+        .map(|(id, channel)| {
+            let mut users = channel.users.iter().map(String::as_str).collect::<Vec<_>>();
+            users.sort();
+            (*id, channel, users.join(", "))
+        })
+        .collect::<Vec<_>>();
+    channels.sort_by_key(|(id, _, _)| *id);
+
+    let mut keys = config
+        .keys
+        .iter()
+        .map(|key| key_hint(key))
+        .collect::<Vec<_>>();
+    keys.sort();
+
+    DashboardPage {
+        config,
+        channels,
+        keys,
+        error,
+    }
+    .render()
+    .unwrap()
+}
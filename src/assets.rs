@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    hash::{DefaultHasher, Hasher},
+    path::{Component, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+
+// served under `/static`, see `asset`
+const EMBEDDED: [(&str, &[u8]); 4] = [
+    ("main.css", include_bytes!("../static/main.css")),
+    ("theme.js", include_bytes!("../static/theme.js")),
+    ("live.js", include_bytes!("../static/live.js")),
+    ("favicon.svg", include_bytes!("../static/favicon.svg")),
+];
+
+// used unless `mermaid.esm.min.mjs` is in the theme folder
+const MERMAID_CDN: &str = "https://cdn.jsdelivr.net/npm/mermaid@11/dist/mermaid.esm.min.mjs";
+
+/// Content of an asset and the hash it is versioned by.
+pub struct Asset {
+    pub content: Bytes,
+    pub version: String,
+}
+
+// theme file to the modification time it was read at
+type ThemeCache = HashMap<PathBuf, (SystemTime, Arc<Asset>)>;
+
+#[derive(Deserialize)]
+pub struct AssetQuery {
+    v: Option<String>,
+}
+
+fn theme() -> PathBuf {
+    PathBuf::from(env::var("CONFIG").expect("missing ENV `CONFIG`")).join("theme")
+}
+
+/// A file in the `theme` folder of the config folder, or else one embedded in the binary.
+///
+/// Theme files are only read again once their modification time changes, as every page links
+/// its assets by version.
+pub fn asset(path: &str) -> Option<Arc<Asset>> {
+    static THEME: OnceLock<Mutex<ThemeCache>> = OnceLock::new();
+    static EMBEDDED_ASSETS: OnceLock<HashMap<&str, Arc<Asset>>> = OnceLock::new();
+
+    let relative = std::path::Path::new(path);
+    // nothing outside the theme folder
+    if path.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let file = theme().join(relative);
+    if let Some(modified) = fs::metadata(&file)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .and_then(|metadata| metadata.modified().ok())
+    {
+        let mut theme = THEME.get_or_init(Default::default).lock().unwrap();
+        if let Some((read, asset)) = theme.get(&file)
+            && *read == modified
+        {
+            return Some(asset.clone());
+        }
+        if let Ok(content) = fs::read(&file) {
+            let asset = Arc::new(Asset {
+                version: version(&content),
+                content: Bytes::from(content),
+            });
+            theme.insert(file, (modified, asset.clone()));
+            return Some(asset);
+        }
+    }
+
+    EMBEDDED_ASSETS
+        .get_or_init(|| {
+            EMBEDDED
+                .iter()
+                .map(|(name, content)| {
+                    (
+                        *name,
+                        Arc::new(Asset {
+                            content: Bytes::from_static(content),
+                            version: version(content),
+                        }),
+                    )
+                })
+                .collect()
+        })
+        .get(path)
+        .cloned()
+}
+
+fn version(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(content);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Link to an asset that changes whenever its content does, so it can be cached for good.
+pub fn asset_url(path: &str) -> String {
+    match asset(path) {
+        Some(asset) => format!("/static/{path}?v={}", asset.version),
+        None => format!("/static/{path}"),
+    }
+}
+
+pub fn mermaid_url() -> String {
+    // not embedded, so only found in the theme folder
+    match asset("mermaid.esm.min.mjs") {
+        Some(asset) => format!("/static/mermaid.esm.min.mjs?v={}", asset.version),
+        None => MERMAID_CDN.to_string(),
+    }
+}
+
+/// The stylesheet used to be served at `/main.css`, kept working for old links to it.
+pub async fn main_css() -> Redirect {
+    Redirect::temporary(&asset_url("main.css"))
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("json" | "map") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+pub async fn serve_asset(
+    Path(path): Path<String>,
+    Query(query): Query<AssetQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(asset) = asset(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = format!("\"{}\"", asset.version);
+    // versioned links never change, anything else is checked against the etag on every use
+    let cache = if query.v.as_deref() == Some(asset.version.as_str()) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    let headers_out = [
+        (header::ETAG, HeaderValue::from_str(&etag).unwrap()),
+        (header::CACHE_CONTROL, HeaderValue::from_static(cache)),
+        (
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(content_type(&path)),
+        ),
+    ];
+
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        return (StatusCode::NOT_MODIFIED, headers_out).into_response();
+    }
+
+    (headers_out, asset.content.clone()).into_response()
+}
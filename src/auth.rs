@@ -0,0 +1,151 @@
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{StatusCode, header, request::Parts},
+};
+use serde::Deserialize;
+
+use crate::{Config, Storage, Visibility};
+
+/// What the token sent with a request grants access to.
+///
+/// The token is read from an `Authorization: Bearer` header, a `token` cookie or a `token`
+/// query parameter, and is either `admin_token` or a share token of one user.
+pub struct Access {
+    pub admin: bool,
+    // the user a share token was created for
+    pub shared: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Access {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let cookie = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .find_map(|cookie| cookie.trim().strip_prefix("token="));
+        let query = Query::<TokenQuery>::try_from_uri(&parts.uri)
+            .ok()
+            .and_then(|query| query.0.token);
+
+        let Some(token) = bearer.or(cookie).map(str::to_string).or(query) else {
+            return Ok(Self::anonymous());
+        };
+
+        Ok(Self {
+            admin: Self::is_admin_token(&token),
+            // the published copy, so requests do not wait on a fetch holding storage
+            shared: Storage::copy().shares.get(&token).cloned(),
+        })
+    }
+}
+
+impl Access {
+    pub fn is_admin_token(token: &str) -> bool {
+        Config::get()
+            .admin_token
+            .as_ref()
+            .is_some_and(|admin| constant_time_eq(admin.as_bytes(), token.as_bytes()))
+    }
+
+    /// No token, what anyone on the website or in discord can see.
+    pub fn anonymous() -> Self {
+        Self {
+            admin: false,
+            shared: None,
+        }
+    }
+
+    /// Whether the page of `user` can be viewed, unknown users can be viewed by anyone.
+    pub fn can_view(&self, user: &str) -> bool {
+        self.admin
+            || self.shared.as_deref() == Some(user)
+            || Visibility::of(user) != Visibility::Private
+    }
+
+    /// Whether `user` shows up in lists of users and live events.
+    pub fn can_list(&self, user: &str) -> bool {
+        self.admin
+            || self.shared.as_deref() == Some(user)
+            || Visibility::of(user) == Visibility::Public
+    }
+
+    pub fn admin(&self) -> Result<(), StatusCode> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+
+    /// Management endpoints are only protected once `admin_token` is set.
+    pub fn manage(&self) -> Result<(), StatusCode> {
+        if self.admin || Config::get().admin_token.is_none() {
+            Ok(())
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+impl Visibility {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Unlisted => "unlisted",
+            Self::Private => "private",
+        }
+    }
+
+    pub fn of(user: &str) -> Self {
+        Config::get()
+            .users
+            .iter()
+            .find(|entry| entry.name == user)
+            .map(|entry| entry.visibility)
+            .unwrap_or_default()
+    }
+}
+
+impl Storage {
+    /// Creates a token granting read access to `user`.
+    pub fn share(&mut self, user: &str) -> String {
+        let mut bytes = [0u8; 16];
+        getrandom::fill(&mut bytes).expect("failed to generate a share token");
+        let token = bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        self.shares.insert(token.clone(), user.to_string());
+        self.save();
+        self.publish();
+        token
+    }
+
+    /// Revokes every share token of `user`.
+    pub fn unshare(&mut self, user: &str) -> usize {
+        let before = self.shares.len();
+        self.shares.retain(|_, shared| shared != user);
+        self.save();
+        self.publish();
+        before - self.shares.len()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use chrono::{Datelike, Days, NaiveDate};
+
+use crate::{Record, Storage};
+
+const CELL: u64 = 13;
+
+/// Seconds played on each day of the retained period, oldest first.
+#[derive(Clone)]
+pub struct Calendar(pub Vec<(NaiveDate, u64)>);
+
+impl Calendar {
+    pub fn build(records: &[Record]) -> Self {
+        let now = chrono::Utc::now();
+        let first = chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0)
+            .unwrap()
+            .date_naive();
+
+        let mut days = first
+            .iter_days()
+            .take_while(|day| *day <= now.date_naive())
+            .map(|day| (day, 0))
+            .collect::<BTreeMap<_, _>>();
+
+        records
+            .iter()
+            .flat_map(|record| record.split_days(now))
+            .for_each(|(start, end)| {
+                if let Some(total) = days.get_mut(&start.date_naive()) {
+                    *total += end.signed_duration_since(start).num_seconds() as u64;
+                }
+            });
+
+        Self(days.into_iter().collect())
+    }
+
+    // 0 to 4, coloured by `main.css`
+    fn level(seconds: u64, max: u64) -> u64 {
+        if seconds == 0 {
+            0
+        } else {
+            1 + seconds * 4 / (max + 1)
+        }
+    }
+}
+
+impl Display for Calendar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some((first, _)) = self.0.first() else {
+            return Ok(());
+        };
+
+        // columns start on mondays
+        let monday = *first - Days::new(first.weekday().num_days_from_monday() as u64);
+        let max = self
+            .0
+            .iter()
+            .map(|(_, seconds)| *seconds)
+            .max()
+            .unwrap_or(0);
+        let weeks = (self.0.last().unwrap().0 - monday).num_weeks() as u64 + 1;
+
+        let cells = self
+            .0
+            .iter()
+            .map(|(day, seconds)| {
+                format!(
+                    r#"<rect x="{}" y="{}" width="11" height="11" rx="2" class="level-{}"><title>{day}: {:.1}h</title></rect>"#,
+                    30 + (*day - monday).num_weeks() as u64 * CELL,
+                    20 + day.weekday().num_days_from_monday() as u64 * CELL,
+                    Self::level(*seconds, max),
+                    *seconds as f32 / 3600.
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let months = self
+            .0
+            .iter()
+            .filter(|(day, _)| day.day() == 1 || day == first)
+            .map(|(day, _)| {
+                format!(
+                    r#"<text x="{}" y="12">{}</text>"#,
+                    30 + (*day - monday).num_weeks() as u64 * CELL,
+                    day.format("%b")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let weekdays = [(0, "Mon"), (2, "Wed"), (4, "Fri")]
+            .iter()
+            .map(|(row, name)| format!(r#"<text x="0" y="{}">{name}</text>"#, 29 + row * CELL))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        f.write_fmt(format_args!(
+            r##"<svg class="calendar" width="{}" height="{}" font-size="10" xmlns="http://www.w3.org/2000/svg">
+{months}
+{weekdays}
+{cells}
+</svg>"##,
+            30 + weeks * CELL,
+            20 + 7 * CELL
+        ))
+    }
+}
@@ -0,0 +1,244 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+    ResolvedValue,
+};
+
+use crate::{Access, Config, Period, Sessions, Storage, format_duration};
+
+pub fn commands() -> Vec<CreateCommand> {
+    vec![
+        CreateCommand::new("online").description("List players who are currently online"),
+        CreateCommand::new("screentime")
+            .description("Show how long a player has been online")
+            .add_option(user_option())
+            .add_option(period_option()),
+        CreateCommand::new("leaderboard")
+            .description("Rank players by time spent online")
+            .add_option(period_option()),
+        CreateCommand::new("lastseen")
+            .description("Show when a player was last online")
+            .add_option(user_option()),
+        CreateCommand::new("subscribe")
+            .description("Get a direct message when a player logs in or out")
+            .add_option(user_option()),
+        CreateCommand::new("unsubscribe")
+            .description("Stop getting direct messages about a player")
+            .add_option(user_option()),
+    ]
+}
+
+fn user_option() -> CreateCommandOption {
+    let option = CreateCommandOption::new(CommandOptionType::String, "user", "Tracked player")
+        .required(true);
+
+    // anyone in the guild can use commands, so they follow the rules of the website without a
+    // token, unlisted players can be picked like their page can be opened by anyone with the link
+    let users = Config::get()
+        .users
+        .iter()
+        .filter(|user| Access::anonymous().can_view(&user.name))
+        .collect::<Vec<_>>();
+
+    // discord allows at most 25 choices
+    if users.len() > 25 {
+        return option;
+    }
+
+    users.iter().fold(option, |option, user| {
+        option.add_string_choice(&user.name, &user.name)
+    })
+}
+
+fn period_option() -> CreateCommandOption {
+    [Period::Day, Period::Week, Period::Month, Period::All]
+        .iter()
+        .fold(
+            CreateCommandOption::new(CommandOptionType::String, "period", "Defaults to week"),
+            |option, period| option.add_string_choice(period.name(), period.name()),
+        )
+}
+
+pub async fn handle(ctx: &Context, command: &CommandInteraction) {
+    let mut user = None;
+    let mut period = Period::Week;
+
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("user", ResolvedValue::String(value)) => user = Some(value),
+            ("period", ResolvedValue::String(value)) => {
+                period = Period::parse(value).unwrap_or(period)
+            }
+            _ => {}
+        }
+    }
+
+    if let ("subscribe" | "unsubscribe", Some(user)) = (command.data.name.as_str(), user) {
+        // storage can be held by a fetch for longer than discord waits for a response
+        let deferred = CreateInteractionResponse::Defer(
+            CreateInteractionResponseMessage::new().ephemeral(true),
+        );
+        if let Err(e) = command.create_response(&ctx.http, deferred).await {
+            tracing::warn!(command = command.data.name, error = %e, "Failed to respond");
+            return;
+        }
+
+        let content = subscribe(
+            command.user.id.get(),
+            user,
+            command.data.name == "subscribe",
+        )
+        .await;
+        if let Err(e) = command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+            .await
+        {
+            tracing::warn!(command = command.data.name, error = %e, "Failed to respond");
+        }
+        return;
+    }
+
+    let content = match (command.data.name.as_str(), user) {
+        ("online", _) => online(),
+        ("screentime", Some(user)) => screentime(user, period),
+        ("leaderboard", _) => leaderboard(period),
+        ("lastseen", Some(user)) => lastseen(user),
+        _ => "Unknown command".to_string(),
+    };
+
+    if let Err(e) = command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().content(content),
+            ),
+        )
+        .await
+    {
+        tracing::warn!(command = command.data.name, error = %e, "Failed to respond");
+    }
+}
+
+fn online() -> String {
+    let storage = Storage::copy();
+    let online = Config::get()
+        .users
+        .iter()
+        .filter(|entry| Access::anonymous().can_list(&entry.name))
+        .filter_map(|entry| {
+            let record = storage.users.get(&entry.name)?.last()?;
+            record
+                .end
+                .is_none()
+                .then(|| format!("**{}** since <t:{}:R>", entry.name, record.beginning / 1000))
+        })
+        .collect::<Vec<_>>();
+
+    if online.is_empty() {
+        "Nobody is online".to_string()
+    } else {
+        online.join("\n")
+    }
+}
+
+fn screentime(user: &str, period: Period) -> String {
+    let storage = Storage::copy();
+    let Some(records) = storage
+        .users
+        .get(user)
+        .filter(|_| Access::anonymous().can_view(user))
+    else {
+        return format!("No such user `{user}`");
+    };
+
+    // built from the published copy, the cached charts would wait on a fetch holding storage
+    let stats = Sessions::build(records).stats();
+    format!(
+        "**{user}** played for **{}** in {}\nMedian session {}, longest session {}",
+        format_duration(storage.playtime(user, period.from())),
+        period.label(),
+        format_duration(stats.median),
+        format_duration(stats.longest)
+    )
+}
+
+fn leaderboard(period: Period) -> String {
+    let mut users = Config::get()
+        .users
+        .iter()
+        .filter(|entry| Access::anonymous().can_list(&entry.name))
+        .map(|entry| {
+            (
+                entry.name.as_str(),
+                Storage::copy().playtime(&entry.name, period.from()),
+            )
+        })
+        .filter(|(_, playtime)| *playtime > 0)
+        .collect::<Vec<_>>();
+    users.sort_by_key(|(_, playtime)| std::cmp::Reverse(*playtime));
+
+    if users.is_empty() {
+        return format!("Nobody played in {}", period.label());
+    }
+
+    format!(
+        "Leaderboard for {}\n{}",
+        period.label(),
+        users
+            .iter()
+            .enumerate()
+            .map(|(index, (name, playtime))| format!(
+                "{}. **{name}** {}",
+                index + 1,
+                format_duration(*playtime)
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn lastseen(user: &str) -> String {
+    match Storage::copy()
+        .users
+        .get(user)
+        .filter(|_| Access::anonymous().can_view(user))
+        .and_then(|records| records.last())
+    {
+        None => format!("No such user `{user}`"),
+        Some(record) => match record.end {
+            None => format!(
+                "**{user}** is online since <t:{}:R>",
+                record.beginning / 1000
+            ),
+            Some(end) => format!("**{user}** was last seen <t:{}:R>", end / 1000),
+        },
+    }
+}
+
+async fn subscribe(subscriber: u64, user: &str, subscribe: bool) -> String {
+    // unsubscribing always works, in case the player was made private since
+    if !Config::get().users.iter().any(|entry| entry.name == user)
+        || subscribe && !Access::anonymous().can_view(user)
+    {
+        return format!("No such user `{user}`");
+    }
+
+    let mut storage = Storage::get().lock().await;
+    let subscribers = storage.subscriptions.entry(user.to_string()).or_default();
+    let changed = if subscribe {
+        subscribers.insert(subscriber)
+    } else {
+        subscribers.remove(&subscriber)
+    };
+    if subscribers.is_empty() {
+        storage.subscriptions.remove(user);
+    }
+    storage.save();
+
+    match (subscribe, changed) {
+        (true, true) => format!("You will get a direct message when **{user}** logs in or out"),
+        (true, false) => format!("You are already subscribed to **{user}**"),
+        (false, true) => format!("You will no longer get direct messages about **{user}**"),
+        (false, false) => format!("You are not subscribed to **{user}**"),
+    }
+}
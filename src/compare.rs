@@ -0,0 +1,134 @@
+use askama::Template;
+
+use crate::{Mermaid, Record, Storage};
+
+pub const PALETTE: [&str; 8] = [
+    "#a6e3a1", "#89b4fa", "#f38ba8", "#f9e2af", "#cba6f7", "#94e2d5", "#fab387", "#eba0ac",
+];
+
+#[derive(Template)]
+#[template(path = "compare.html")]
+pub struct Compare {
+    users: Vec<(String, Mermaid, Summary)>,
+}
+
+pub struct Summary {
+    pub total: u64,
+    pub sessions: usize,
+    pub last_seen: Option<u64>,
+}
+
+impl Summary {
+    pub fn build(records: &[Record]) -> Self {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+
+        Self {
+            total: records
+                .iter()
+                .map(|record| record.end.unwrap_or(now).saturating_sub(record.beginning) / 1000)
+                .sum(),
+            sessions: records.len(),
+            last_seen: records.last().and_then(|record| record.end),
+        }
+    }
+
+    pub fn hours_per_day(&self) -> f32 {
+        let first_fetch =
+            chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0).unwrap();
+        self.total as f32 / ((chrono::Utc::now() - first_fetch).num_days() + 1) as f32 / 3600.
+    }
+}
+
+impl Compare {
+    pub fn new(usernames: &[&str]) -> Self {
+        Self {
+            users: usernames
+                .iter()
+                .filter_map(|name| {
+                    Storage::copy().users.get(*name).map(|records| {
+                        (
+                            name.to_string(),
+                            // the cached charts would wait on a fetch holding storage
+                            Mermaid::build(records),
+                            Summary::build(records),
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn palette(&self) -> String {
+        PALETTE
+            .iter()
+            .cycle()
+            .take(self.users.len())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn timeofday(&self) -> String {
+        format!(
+            r#"%%{{init: {{"themeVariables": {{"xyChart": {{"plotColorPalette": "{}"}}}}}}}}%%
+xychart-beta
+    title "Time of day (GMT)"
+    x-axis [00, 01, 02, 03, 04, 05, 06, 07, 08, 09, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23]
+    y-axis "Avg. minutes online"
+{}
+"#,
+            self.palette(),
+            self.users
+                .iter()
+                .map(|(_, mermaid, _)| format!(
+                    "    line {}",
+                    serde_json::to_string(&mermaid.timeofday.averages()).unwrap()
+                ))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+
+    fn daily(&self) -> String {
+        format!(
+            r#"%%{{init: {{"themeVariables": {{"xyChart": {{"plotColorPalette": "{}"}}}}}}}}%%
+xychart-beta
+    title "Day of week"
+    x-axis [Mon, Tue, Wed, Thu, Fri, Sat, Sun]
+    y-axis "Avg. hours online"
+{}
+"#,
+            self.palette(),
+            self.users
+                .iter()
+                .map(|(_, mermaid, _)| format!(
+                    "    line {}",
+                    serde_json::to_string(&mermaid.daily.averages()).unwrap()
+                ))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+
+    fn last_seen(&self, name: &str, summary: &Summary) -> String {
+        if Storage::is_online(name) {
+            "online".to_string()
+        } else if let Some(last_seen) = summary.last_seen {
+            let s = chrono::DateTime::from_timestamp_millis(last_seen as i64)
+                .unwrap()
+                .to_rfc2822();
+            s[..s.len() - 5].to_string()
+        } else {
+            "never".to_string()
+        }
+    }
+
+    /// `None` if none of the users have records.
+    pub fn html(&self) -> Option<String> {
+        if self.users.is_empty() {
+            return None;
+        }
+
+        Some(self.render().unwrap())
+    }
+}
@@ -3,11 +3,38 @@ use std::{
     env, fs,
     io::Write,
     path::PathBuf,
-    sync::OnceLock,
+    ptr,
+    sync::{
+        Mutex,
+        atomic::{AtomicPtr, Ordering},
+    },
 };
 
 use serde::{Deserialize, Serialize};
 
+use crate::{BroadcastEvent, write_atomic};
+
+// replaced by `Config::update`, see `Config::get`
+static CONFIG: AtomicPtr<Config> = AtomicPtr::new(ptr::null_mut());
+// master.json as it was loaded or last saved, held while editing
+static SAVED: Mutex<String> = Mutex::new(String::new());
+
+// paths of pages and endpoints, which would shadow the page of a user with the same name
+const RESERVED: [&str; 12] = [
+    "compare",
+    "overlap",
+    "events",
+    "api",
+    "export",
+    "metrics",
+    "healthz",
+    "readyz",
+    "admin",
+    "static",
+    "main.css",
+    "favicon.ico",
+];
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub keys: HashSet<String>,
@@ -18,20 +45,176 @@ pub struct Config {
     pub merge: u64,
     pub expire: u64,
     pub users: Vec<Identifier>,
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    // username to their screentime limits
+    #[serde(default)]
+    pub limits: HashMap<String, Limits>,
+    // direct messages sent to `/subscribe` subscribers
+    #[serde(default)]
+    pub subscription_templates: Templates,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub online_role: Option<OnlineRole>,
+    // minimum milliseconds between messages to the same discord channel
+    #[serde(default = "Config::default_discord_rate_limit")]
+    pub discord_rate_limit: u64,
+    // seconds to hold back offline notifications, dropped along with the resume if the user
+    // logs back in within `merge`
+    #[serde(default)]
+    pub offline_grace: u64,
+    // fetches older than this many intervals fail the health checks
+    #[serde(default = "Config::default_health_intervals")]
+    pub health_intervals: u64,
+    #[serde(default)]
+    pub log: Logging,
+    // grants access to management endpoints and private users, which are open if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct BroadcastChannel {
+    #[serde(flatten)]
+    pub templates: Templates,
+    pub users: HashSet<String>,
+    #[serde(default)]
+    pub embed: bool,
+    #[serde(default)]
+    pub colours: Colours,
+    // keep a single pinned message listing who is online instead of posting every event
+    #[serde(default)]
+    pub status: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Digest>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Digest {
+    // time of day to post at, "HH:MM" in UTC
+    pub time: String,
+    pub period: DigestPeriod,
+}
+
+// weekly digests are posted on mondays
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Templates {
     pub online: String,
     pub resumed: String,
     pub offline: String,
-    pub users: HashSet<String>,
+    pub limit: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    DiscordWebhook {
+        url: String,
+        users: HashSet<String>,
+        #[serde(flatten)]
+        templates: Templates,
+        #[serde(default)]
+        embed: bool,
+        #[serde(default)]
+        colours: Colours,
+    },
+    // posts the event and rendered message as json
+    Webhook {
+        url: String,
+        users: HashSet<String>,
+        #[serde(flatten)]
+        templates: Templates,
+    },
+    Ntfy {
+        #[serde(default = "NotifierConfig::ntfy_server")]
+        server: String,
+        topic: String,
+        users: HashSet<String>,
+        #[serde(flatten)]
+        templates: Templates,
+    },
+    Stdout {
+        users: HashSet<String>,
+        #[serde(flatten)]
+        templates: Templates,
+    },
+}
+
+// embed colours as "#rrggbb"
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Colours {
+    pub online: String,
+    pub resumed: String,
+    pub offline: String,
+    pub limit: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Limits {
+    // hours
+    pub daily: Option<f32>,
+    pub weekly: Option<f32>,
+    pub session: Option<f32>,
+    // time of day the user may be online, in UTC
+    pub allowed: Option<Window>,
+    // fraction of a limit at which a warning is sent
+    #[serde(default = "Limits::default_warn")]
+    pub warn: f32,
+}
+
+// "HH:MM", wraps around midnight if `from` is after `to`
+#[derive(Serialize, Deserialize)]
+pub struct Window {
+    pub from: String,
+    pub to: String,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Identifier {
     pub uuid: String,
     pub name: String,
+    // linked discord user id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discord: Option<u64>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+// who can see a user's page on the website
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    // listed on the front page
+    #[default]
+    Public,
+    // viewable by anyone with the link
+    Unlisted,
+    // viewable by the admin and share links only
+    Private,
+}
+
+// role given to linked discord members while they are online
+#[derive(Serialize, Deserialize)]
+pub struct OnlineRole {
+    pub guild: u64,
+    pub role: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Logging {
+    // tracing filter directives, overridden by `RUST_LOG`
+    pub level: String,
+    // one JSON object per line instead of human readable text
+    pub json: bool,
 }
 
 impl Default for Config {
@@ -45,15 +228,187 @@ impl Default for Config {
             merge: 60,
             expire: 70,
             users: Vec::new(),
+            notifiers: Vec::new(),
+            limits: HashMap::new(),
+            subscription_templates: Templates::default(),
+            online_role: None,
+            discord_rate_limit: Self::default_discord_rate_limit(),
+            offline_grace: 0,
+            health_intervals: Self::default_health_intervals(),
+            log: Logging::default(),
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            level: "warn,hypixel_screentime=info".to_string(),
+            json: false,
+        }
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            online: "Online: {user}".to_string(),
+            resumed: "Online: {user} (resumed session)".to_string(),
+            offline: "Offline: {user} (played for {duration})".to_string(),
+            limit: "Limit: {user} {status} the {limit} limit ({used} of {max})".to_string(),
+        }
+    }
+}
+
+impl Default for Colours {
+    fn default() -> Self {
+        Self {
+            online: "#a6e3a1".to_string(),
+            resumed: "#f9e2af".to_string(),
+            offline: "#f38ba8".to_string(),
+            limit: "#fab387".to_string(),
+        }
+    }
+}
+
+impl Templates {
+    pub fn get(&self, event: &BroadcastEvent) -> &str {
+        match event {
+            BroadcastEvent::Online { resumed: false, .. } => &self.online,
+            BroadcastEvent::Online { resumed: true, .. } => &self.resumed,
+            BroadcastEvent::Offline { .. } => &self.offline,
+            BroadcastEvent::Limit { .. } => &self.limit,
         }
     }
 }
 
+impl Limits {
+    fn default_warn() -> f32 {
+        0.8
+    }
+}
+
+impl NotifierConfig {
+    fn ntfy_server() -> String {
+        "https://ntfy.sh".to_string()
+    }
+}
+
+impl Colours {
+    pub fn get(&self, event: &BroadcastEvent) -> u32 {
+        let colour = match event {
+            BroadcastEvent::Online { resumed: false, .. } => &self.online,
+            BroadcastEvent::Online { resumed: true, .. } => &self.resumed,
+            BroadcastEvent::Offline { .. } => &self.offline,
+            BroadcastEvent::Limit { .. } => &self.limit,
+        };
+        u32::from_str_radix(colour.trim_start_matches('#'), 16).unwrap_or(0)
+    }
+}
+
 impl Config {
+    fn default_discord_rate_limit() -> u64 {
+        1000
+    }
+
+    fn default_health_intervals() -> u64 {
+        3
+    }
+
     pub fn get() -> &'static Config {
-        static CONFIG: OnceLock<Config> = OnceLock::new();
+        let config = CONFIG.load(Ordering::Acquire);
+        if !config.is_null() {
+            return unsafe { &*config };
+        }
+
+        let config = Box::into_raw(Box::new(Self::init()));
+        match CONFIG.compare_exchange(ptr::null_mut(), config, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => unsafe { &*config },
+            // another thread loaded it first
+            Err(existing) => {
+                drop(unsafe { Box::from_raw(config) });
+                unsafe { &*existing }
+            }
+        }
+    }
 
-        CONFIG.get_or_init(Self::init)
+    /// Applies `edit` to the current config, saves it to `master.json` and makes it current.
+    ///
+    /// The previous config is leaked, as `&'static` references to it are held everywhere.
+    pub async fn update(
+        edit: impl FnOnce(&mut Config) -> Result<(), String> + Send + 'static,
+    ) -> Result<(), String> {
+        tokio::task::spawn_blocking(|| Self::update_blocking(edit))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    fn update_blocking(edit: impl FnOnce(&mut Config) -> Result<(), String>) -> Result<(), String> {
+        let mut saved = SAVED.lock().unwrap();
+        let current = Self::get();
+
+        // saving would silently apply or overwrite changes made by hand
+        let path = PathBuf::from(env::var("CONFIG").expect("missing ENV `CONFIG`"));
+        if fs::read_to_string(path.join("master.json")).map_err(|e| e.to_string())? != *saved {
+            return Err("master.json was changed since it was loaded, restart to apply those changes before editing here".to_string());
+        }
+
+        let mut config =
+            serde_json::from_value::<Config>(serde_json::to_value(current).unwrap()).unwrap();
+        edit(&mut config)?;
+        config.validate(Some(current))?;
+
+        let content = serde_json::to_string_pretty(&config).unwrap();
+        write_atomic(&path.join("master.json"), content.as_bytes())
+            .map_err(|e| format!("failed to save master.json - {e}"))?;
+
+        *saved = content;
+        CONFIG.store(Box::into_raw(Box::new(config)), Ordering::Release);
+        Ok(())
+    }
+
+    /// Checks the config, users also in `previous` are left alone so an entry that was already
+    /// there does not block every other edit.
+    pub fn validate(&self, previous: Option<&Config>) -> Result<(), String> {
+        if self.keys.is_empty() {
+            return Err("at least one API key is required".to_string());
+        }
+        if self.keys.iter().any(|key| key.trim().is_empty()) {
+            return Err("API keys cannot be empty".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for user in self.users.iter() {
+            if !names.insert(&user.name) {
+                return Err(format!("`{}` is tracked twice", user.name));
+            }
+            if previous.is_some_and(|previous| {
+                previous
+                    .users
+                    .iter()
+                    .any(|entry| entry.name == user.name && entry.uuid == user.uuid)
+            }) {
+                continue;
+            }
+
+            if user.name.trim().is_empty()
+                || user.name.contains(['/', ',', '?', '#'])
+                || RESERVED.contains(&user.name.as_str())
+            {
+                return Err(format!("`{}` is not a valid name", user.name));
+            }
+            let digits = user.uuid.replace('-', "");
+            if digits.len() != 32 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "`{}` is not a valid uuid, expected 32 hexadecimal digits",
+                    user.uuid
+                ));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn init() -> Config {
@@ -78,6 +433,10 @@ impl Config {
                 .unwrap();
         }
 
-        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+        let content = fs::read_to_string(&path).unwrap();
+        let config = serde_json::from_str::<Config>(&content).unwrap();
+
+        *SAVED.lock().unwrap() = content;
+        config
     }
 }
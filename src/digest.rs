@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, Utc};
+
+use crate::{BroadcastChannel, Digest, DigestPeriod, Outbox, Outgoing, Storage, format_duration};
+
+impl Digest {
+    /// Next time the digest is due, strictly after `now`.
+    pub fn next(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let time = NaiveTime::parse_from_str(&self.time, "%H:%M").unwrap_or(NaiveTime::MIN);
+        let mut next = now.with_time(time).unwrap();
+
+        while next <= now
+            || matches!(self.period, DigestPeriod::Weekly)
+                && next.weekday().num_days_from_monday() != 0
+        {
+            next += chrono::Duration::days(1);
+        }
+
+        next
+    }
+
+    /// Posts the digest to `channel` on schedule, forever.
+    pub async fn schedule(&self, channel: u64, options: &BroadcastChannel) {
+        if NaiveTime::parse_from_str(&self.time, "%H:%M").is_err() {
+            tracing::warn!(
+                channel,
+                time = self.time,
+                "Invalid digest time, expected \"HH:MM\", digest disabled"
+            );
+            return;
+        }
+
+        loop {
+            let now = Utc::now();
+            let sleep = (self.next(now) - now).to_std().unwrap_or(Duration::ZERO);
+            tokio::time::sleep(sleep).await;
+
+            let content = self.content(options, &*Storage::get().lock().await);
+            Outbox::get().lock().await.push(Outgoing {
+                channel,
+                direct: false,
+                content,
+                embed: None,
+                user: String::new(),
+                kind: "digest".to_string(),
+                created: Utc::now().timestamp_millis() as u64,
+                attempts: 0,
+                next_attempt: 0,
+            });
+        }
+    }
+
+    pub fn content(&self, options: &BroadcastChannel, storage: &Storage) -> String {
+        let (days, title) = match self.period {
+            DigestPeriod::Daily => (1, "Daily digest for"),
+            DigestPeriod::Weekly => (7, "Weekly digest for the week of"),
+        };
+        let now = Utc::now();
+        let to = match self.period {
+            DigestPeriod::Daily => now.with_time(NaiveTime::MIN).unwrap(),
+            DigestPeriod::Weekly => {
+                now.with_time(NaiveTime::MIN).unwrap()
+                    - Days::new(now.weekday().num_days_from_monday() as u64)
+            }
+        };
+        let from = to - Days::new(days);
+        let previous = from - Days::new(days);
+        let millis = |time: DateTime<Utc>| time.timestamp_millis() as u64;
+
+        let mut users = options
+            .users
+            .iter()
+            .map(|user| {
+                (
+                    user.as_str(),
+                    storage.playtime_between(user, millis(from), millis(to)),
+                    storage.playtime_between(user, millis(previous), millis(from)),
+                )
+            })
+            .filter(|(_, current, previous)| *current > 0 || *previous > 0)
+            .collect::<Vec<_>>();
+        users.sort_by_key(|(_, current, _)| std::cmp::Reverse(*current));
+
+        if users.is_empty() {
+            return format!("**{title}** {}\nNobody played", from.format("%a %d %b"));
+        }
+
+        let longest = options
+            .users
+            .iter()
+            .flat_map(|user| {
+                storage
+                    .users
+                    .get(user)
+                    .into_iter()
+                    .flatten()
+                    .filter(|record| (millis(from)..millis(to)).contains(&record.beginning))
+                    .filter_map(move |record| {
+                        Some((user, record.end?.saturating_sub(record.beginning) / 1000))
+                    })
+            })
+            .max_by_key(|(_, length)| *length);
+
+        let total = users.iter().map(|(_, current, _)| current).sum::<u64>();
+        let total_previous = users.iter().map(|(_, _, previous)| previous).sum::<u64>();
+
+        format!(
+            "**{title}** {}\n{}\n{}Total {} ({} vs previous {})",
+            from.format("%a %d %b"),
+            users
+                .iter()
+                .enumerate()
+                .map(|(index, (user, current, previous))| {
+                    format!(
+                        "{}. **{user}** {} ({})",
+                        index + 1,
+                        format_duration(*current),
+                        Self::difference(*current, *previous)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            longest
+                .map(|(user, length)| format!(
+                    "Longest session **{user}** {}\n",
+                    format_duration(length)
+                ))
+                .unwrap_or_default(),
+            format_duration(total),
+            Self::difference(total, total_previous),
+            match self.period {
+                DigestPeriod::Daily => "day",
+                DigestPeriod::Weekly => "week",
+            }
+        )
+    }
+
+    fn difference(current: u64, previous: u64) -> String {
+        if current >= previous {
+            format!("+{}", format_duration(current - previous))
+        } else {
+            format!("-{}", format_duration(previous - current))
+        }
+    }
+}
@@ -1,86 +1,357 @@
-use std::{sync::OnceLock, time::Duration};
+use std::{
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
 
 use serenity::{
     Client,
-    all::{ChannelId, GatewayIntents},
+    all::{
+        ActivityData, ChannelId, ConnectionStage, Context, EditMessage, EventHandler,
+        GatewayIntents, GuildId, Http, Interaction, MessageId, Ready, RoleId, ShardManager,
+        ShardMessenger, ShardStageUpdateEvent, UserId,
+    },
+    async_trait,
+    http::HttpError,
 };
-use tokio::sync::mpsc;
 
-use crate::Config;
+use crate::{
+    Access, BroadcastChannel, BroadcastEvent, Config, Embed, Notifier, NotifyResult, Outbox,
+    Outgoing, Placeholders, Storage, commands, format_duration,
+};
+
+// set once the discord client is created
+pub static HTTP: OnceLock<Arc<Http>> = OnceLock::new();
+// set once the gateway is ready
+static SHARD: OnceLock<ShardMessenger> = OnceLock::new();
+// set once the discord client is created, to shut it down
+static SHARD_MANAGER: OnceLock<Arc<ShardManager>> = OnceLock::new();
+// whether the gateway connection is currently up
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+// discord json error code of a deleted or missing message
+const UNKNOWN_MESSAGE: isize = 10008;
+
+/// Status and discord json error code of a request discord rejected.
+pub fn discord_error(e: &serenity::Error) -> Option<(u16, isize)> {
+    match e {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+            Some((response.status_code.as_u16(), response.error.code))
+        }
+        _ => None,
+    }
+}
+
+/// Posts events to `broadcast_channels` through the discord bot.
+pub struct DiscordBot;
 
-// username, online
-pub static SENDER: OnceLock<mpsc::UnboundedSender<BroadcastEvent>> = OnceLock::new();
+struct Handler;
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let _ = SHARD.set(ctx.shard.clone());
+        CONNECTED.store(true, Ordering::Relaxed);
+        update_presence(&*Storage::get().lock().await);
+
+        for guild in ready.guilds {
+            if let Err(e) = guild.id.set_commands(&ctx.http, commands::commands()).await {
+                tracing::warn!(guild = %guild.id, error = %e, "Failed to register commands");
+            }
+        }
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        CONNECTED.store(event.new == ConnectionStage::Connected, Ordering::Relaxed);
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Command(command) = interaction {
+            commands::handle(&ctx, &command).await;
+        }
+    }
+}
+
+impl Notifier for DiscordBot {
+    fn name(&self) -> String {
+        "discord bot".to_string()
+    }
+
+    fn notify<'a>(
+        &'a self,
+        event: &'a BroadcastEvent,
+        placeholders: &'a Placeholders,
+    ) -> BoxFuture<'a, NotifyResult> {
+        Box::pin(async move {
+            let Some(http) = HTTP.get() else {
+                return Ok(());
+            };
+            let mut result = Ok(());
+
+            if let BroadcastEvent::Online { .. } | BroadcastEvent::Offline { .. } = event {
+                update_presence(&*Storage::get().lock().await);
+
+                if let Err(e) = update_role(http, event).await {
+                    result = Err(format!("role - {e}").into());
+                }
+            }
+
+            for (channel, options) in Config::get().broadcast_channels.iter() {
+                if !options.users.contains(event.user()) {
+                    continue;
+                }
+
+                if options.status {
+                    update_status(http, *channel, options).await;
+                    continue;
+                }
+
+                Outbox::get().lock().await.push(Outgoing {
+                    channel: *channel,
+                    direct: false,
+                    content: placeholders.render(options.templates.get(event)),
+                    embed: options.embed.then(|| Embed {
+                        author: event.user().to_string(),
+                        icon_url: placeholders.get("avatar").to_string(),
+                        colour: options.colours.get(event),
+                    }),
+                    user: event.user().to_string(),
+                    kind: event.kind().to_string(),
+                    created: chrono::Utc::now().timestamp_millis() as u64,
+                    attempts: 0,
+                    next_attempt: 0,
+                });
+            }
+
+            // subscribed before the player was made private
+            if let BroadcastEvent::Online { .. } | BroadcastEvent::Offline { .. } = event
+                && Access::anonymous().can_view(event.user())
+            {
+                let subscribers = Storage::get()
+                    .lock()
+                    .await
+                    .subscriptions
+                    .get(event.user())
+                    .cloned()
+                    .unwrap_or_default();
+                let templates = &Config::get().subscription_templates;
+                let mut outbox = Outbox::get().lock().await;
+
+                for subscriber in subscribers {
+                    outbox.push(Outgoing {
+                        channel: subscriber,
+                        direct: true,
+                        content: placeholders.render(templates.get(event)),
+                        embed: None,
+                        user: event.user().to_string(),
+                        kind: event.kind().to_string(),
+                        created: chrono::Utc::now().timestamp_millis() as u64,
+                        attempts: 0,
+                        next_attempt: 0,
+                    });
+                }
+            }
 
-pub enum BroadcastEvent {
-    Online { user: String, resumed: bool },
-    Offline { user: String, duration: Duration },
+            result
+        })
+    }
 }
 
 pub async fn start_discord() {
     let config = Config::get();
 
     if config.discord_token.is_empty() {
-        println!("Token empty, discord client not started");
+        tracing::info!("Token empty, discord client not started");
         return;
     }
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES;
+    let intents =
+        GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES;
 
     let mut client = Client::builder(&config.discord_token, intents)
+        .event_handler(Handler)
         .await
         .expect("Err creating client");
 
-    let (tx, mut rx) = mpsc::unbounded_channel();
-    SENDER.set(tx).unwrap();
+    HTTP.set(client.http.clone()).unwrap();
+    let _ = SHARD_MANAGER.set(client.shard_manager.clone());
+
     let http = client.http.clone();
+    tokio::task::spawn(async move { Outbox::deliver(&http).await });
 
+    let http = client.http.clone();
     tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
         loop {
-            let event = rx.recv().await.unwrap();
-
-            match event {
-                BroadcastEvent::Online { user, resumed } => {
-                    for (channel, options) in config.broadcast_channels.iter() {
-                        if !options.users.contains(&user) {
-                            continue;
-                        }
-
-                        if resumed {
-                            let _ = ChannelId::new(*channel)
-                                .say(http.clone(), options.resumed.replace("{user}", &user))
-                                .await;
-                        } else {
-                            let _ = ChannelId::new(*channel)
-                                .say(http.clone(), options.online.replace("{user}", &user))
-                                .await;
-                        }
-                    }
-                }
-                BroadcastEvent::Offline { user, duration } => {
-                    let seconds = duration.as_secs() % 60;
-                    let minutes = (duration.as_secs() / 60) % 60;
-                    let hours = (duration.as_secs() / 60) / 60;
-                    let duration = format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, seconds);
-                    for (channel, options) in config.broadcast_channels.iter() {
-                        if !options.users.contains(&user) {
-                            continue;
-                        }
-
-                        let _ = ChannelId::new(*channel)
-                            .say(
-                                http.clone(),
-                                options
-                                    .offline
-                                    .replace("{user}", &user)
-                                    .replace("{duration}", &duration),
-                            )
-                            .await;
-                    }
+            interval.tick().await;
+            // channels can be changed from the admin page
+            for (channel, options) in Config::get().broadcast_channels.iter() {
+                if options.status {
+                    update_status(&http, *channel, options).await;
                 }
             }
         }
     });
+    for (channel, options) in config.broadcast_channels.iter() {
+        if let Some(digest) = &options.digest {
+            tokio::task::spawn(digest.schedule(*channel, options));
+        }
+    }
     tokio::task::spawn(async move {
         client.start().await.unwrap();
     });
 }
+
+/// Sends what is left in the outbox, then disconnects from the gateway.
+pub async fn stop_discord() {
+    let Some(shard_manager) = SHARD_MANAGER.get() else {
+        return;
+    };
+
+    Outbox::flush(Duration::from_secs(10)).await;
+    shard_manager.shutdown_all().await;
+    tracing::info!("Discord client stopped");
+}
+
+/// Whether the bot is connected to the gateway, `None` if it is not enabled.
+pub fn discord_connected() -> Option<bool> {
+    HTTP.get().map(|_| CONNECTED.load(Ordering::Relaxed))
+}
+
+/// Sets the bot activity to the number of tracked users online.
+fn update_presence(storage: &Storage) {
+    let Some(shard) = SHARD.get() else {
+        return;
+    };
+
+    // visible to everyone in the guild, so only public players are counted
+    let users = Config::get()
+        .users
+        .iter()
+        .filter(|entry| Access::anonymous().can_list(&entry.name))
+        .collect::<Vec<_>>();
+    let online = users
+        .iter()
+        .filter(|entry| {
+            storage
+                .users
+                .get(&entry.name)
+                .and_then(|records| records.last())
+                .is_some_and(|record| record.end.is_none())
+        })
+        .count();
+    shard.set_activity(Some(ActivityData::watching(format!(
+        "{online}/{} online",
+        users.len()
+    ))));
+}
+
+/// Gives the linked discord member `online_role` while they are online.
+async fn update_role(http: &Http, event: &BroadcastEvent) -> serenity::Result<()> {
+    let config = Config::get();
+    let (Some(role), Some(member)) = (
+        &config.online_role,
+        config
+            .users
+            .iter()
+            .find(|entry| entry.name == event.user())
+            .and_then(|entry| entry.discord),
+    ) else {
+        return Ok(());
+    };
+
+    let (guild, member, role) = (
+        GuildId::new(role.guild),
+        UserId::new(member),
+        RoleId::new(role.role),
+    );
+    match event {
+        BroadcastEvent::Online { .. } => {
+            http.add_member_role(guild, member, role, Some("Online on Hypixel"))
+                .await
+        }
+        _ => {
+            http.remove_member_role(guild, member, role, Some("Offline on Hypixel"))
+                .await
+        }
+    }
+}
+
+fn status_content(options: &BroadcastChannel, storage: &Storage) -> String {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    let mut online = options
+        .users
+        .iter()
+        .filter_map(|user| {
+            let record = storage.users.get(user)?.last()?;
+            record.end.is_none().then_some((user, record.beginning))
+        })
+        .collect::<Vec<_>>();
+    online.sort_by_key(|(_, beginning)| *beginning);
+
+    format!(
+        "**Online ({}/{})** updated <t:{}:R>\n{}",
+        online.len(),
+        options.users.len(),
+        now / 1000,
+        if online.is_empty() {
+            "Nobody is online".to_string()
+        } else {
+            online
+                .iter()
+                .map(|(user, beginning)| {
+                    format!(
+                        "**{user}** for {}",
+                        format_duration(now.saturating_sub(*beginning) / 1000)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    )
+}
+
+/// Edits the status message of the channel in place, a new one is sent and pinned if missing.
+async fn update_status(http: &Http, channel: u64, options: &BroadcastChannel) {
+    let (content, message) = {
+        let storage = Storage::get().lock().await;
+        (
+            status_content(options, &storage),
+            storage.status_messages.get(&channel).copied(),
+        )
+    };
+
+    if let Some(message) = message {
+        match ChannelId::new(channel)
+            .edit_message(
+                http,
+                MessageId::new(message),
+                EditMessage::new().content(&content),
+            )
+            .await
+        {
+            Ok(_) => return,
+            // deleted, send a new one below
+            Err(e) if discord_error(&e).is_some_and(|(_, code)| code == UNKNOWN_MESSAGE) => {}
+            // anything else could be temporary, a new message would leave a duplicate pinned
+            Err(e) => {
+                tracing::warn!(channel, error = %e, "Failed to edit status message");
+                return;
+            }
+        }
+    }
+
+    match ChannelId::new(channel).say(http, content).await {
+        Ok(message) => {
+            let _ = message.pin(http).await;
+            let mut storage = Storage::get().lock().await;
+            storage.status_messages.insert(channel, message.id.get());
+            storage.save();
+        }
+        Err(e) => tracing::warn!(channel, error = %e, "Failed to send status message"),
+    }
+}
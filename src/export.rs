@@ -0,0 +1,223 @@
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::{Period, Record, Storage};
+
+/// Which records to export, shared by the export pages and command.
+#[derive(Deserialize, Default)]
+pub struct ExportFilter {
+    // comma separated usernames, every user if missing
+    pub users: Option<String>,
+    // `YYYY-MM-DD` (UTC) or RFC 3339, both inclusive
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // `day`, `week`, `month` or `all`, ignored if `from` is set
+    pub period: Option<String>,
+}
+
+impl ExportFilter {
+    /// Parses `--users`, `--from`, `--to` and `--period` command line flags.
+    pub fn parse_args(args: &[String]) -> Result<Self, String> {
+        let mut filter = Self::default();
+        let mut args = args.iter();
+
+        while let Some(flag) = args.next() {
+            let field = match flag.as_str() {
+                "--users" => &mut filter.users,
+                "--from" => &mut filter.from,
+                "--to" => &mut filter.to,
+                "--period" => &mut filter.period,
+                _ => return Err(format!("unknown flag `{flag}`")),
+            };
+            *field = Some(
+                args.next()
+                    .ok_or(format!("missing value for `{flag}`"))?
+                    .clone(),
+            );
+        }
+
+        Ok(filter)
+    }
+
+    fn time(time: &str, end: bool) -> Result<u64, String> {
+        if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+            let date = if end { date + Days::new(1) } else { date };
+            return Ok(date
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis() as u64);
+        }
+
+        DateTime::parse_from_rfc3339(time)
+            .map(|time| time.timestamp_millis() as u64)
+            .map_err(|_| format!("invalid time `{time}`, expected YYYY-MM-DD or RFC 3339"))
+    }
+
+    /// Range to export in unix millis.
+    fn range(&self) -> Result<(u64, u64), String> {
+        let from = match (&self.from, &self.period) {
+            (Some(from), _) => Self::time(from, false)?,
+            (None, Some(period)) => Period::parse(period)
+                .ok_or(format!("invalid period `{period}`"))?
+                .from(),
+            (None, None) => 0,
+        };
+        let to = match &self.to {
+            Some(to) => Self::time(to, true)?,
+            None => u64::MAX,
+        };
+
+        Ok((from, to))
+    }
+
+    /// Records overlapping the range, oldest first.
+    pub fn sessions<'a>(&self, storage: &'a Storage) -> Result<Vec<(&'a str, &'a Record)>, String> {
+        let (from, to) = self.range()?;
+        let now = Utc::now().timestamp_millis() as u64;
+        let users = self
+            .users
+            .as_ref()
+            .map(|users| users.split(',').map(str::trim).collect::<Vec<_>>());
+
+        let mut sessions = storage
+            .users
+            .iter()
+            .filter(|(user, _)| {
+                users
+                    .as_ref()
+                    .is_none_or(|users| users.contains(&user.as_str()))
+            })
+            .flat_map(|(user, records)| records.iter().map(move |record| (user.as_str(), record)))
+            .filter(|(_, record)| record.beginning < to && record.end.unwrap_or(now) >= from)
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|(user, record)| (record.beginning, *user));
+
+        Ok(sessions)
+    }
+}
+
+fn rfc3339(millis: u64) -> String {
+    DateTime::from_timestamp_millis(millis as i64)
+        .unwrap()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn duration(record: &Record) -> u64 {
+    record
+        .end
+        .unwrap_or(Utc::now().timestamp_millis() as u64)
+        .saturating_sub(record.beginning)
+        / 1000
+}
+
+/// One row per session, `end` is empty while the session is ongoing.
+pub fn csv(sessions: &[(&str, &Record)]) -> String {
+    let quote = |field: &str| {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    };
+
+    std::iter::once("user,start,end,duration".to_string())
+        .chain(sessions.iter().map(|(user, record)| {
+            format!(
+                "{},{},{},{}",
+                quote(user),
+                rfc3339(record.beginning),
+                record.end.map(rfc3339).unwrap_or_default(),
+                duration(record)
+            )
+        }))
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// One JSON object per session, with the record fields in unix millis.
+pub fn jsonl(sessions: &[(&str, &Record)]) -> String {
+    sessions
+        .iter()
+        .map(|(user, record)| {
+            serde_json::json!({
+                "user": user,
+                "beginning": record.beginning,
+                "session_begin": record.session_begin,
+                "end": record.end,
+                "duration": duration(record),
+            })
+            .to_string()
+                + "\n"
+        })
+        .collect()
+}
+
+/// Calendar with every session of `user` as an event.
+pub fn ics(user: &str, records: &[Record]) -> String {
+    let escape = |text: &str| {
+        text.replace('\\', "\\\\")
+            .replace(';', "\\;")
+            .replace(',', "\\,")
+            .replace('\n', "\\n")
+    };
+    let format = |millis: u64| {
+        DateTime::from_timestamp_millis(millis as i64)
+            .unwrap()
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string()
+    };
+    let now = Utc::now().timestamp_millis() as u64;
+    let user = escape(user);
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//hypixel-screentime//EN".to_string(),
+        format!("X-WR-CALNAME:{user} on Hypixel"),
+    ];
+    for record in records {
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!(
+                "UID:{}-{}@hypixel-screentime",
+                record.beginning,
+                user.replace(' ', "_")
+            ),
+            format!("DTSTAMP:{}", format(now)),
+            format!("DTSTART:{}", format(record.beginning)),
+            format!("DTEND:{}", format(record.end.unwrap_or(now))),
+            format!(
+                "SUMMARY:{user} on Hypixel{}",
+                if record.end.is_none() {
+                    " (online)"
+                } else {
+                    ""
+                }
+            ),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| format!("{line}\r\n")).collect()
+}
+
+/// `export <csv|jsonl> [flags]` or `export ics <user>`, prints to stdout.
+pub fn export_command(args: &[String]) -> Result<String, String> {
+    let storage = Storage::read()?;
+
+    match args.first().map(String::as_str) {
+        Some("csv") => Ok(csv(&ExportFilter::parse_args(&args[1..])?.sessions(&storage)?)),
+        Some("jsonl") => Ok(jsonl(&ExportFilter::parse_args(&args[1..])?.sessions(&storage)?)),
+        Some("ics") => {
+            let user = args.get(1).ok_or("missing user")?;
+            let records = storage
+                .users
+                .get(user)
+                .ok_or(format!("no such user `{user}`"))?;
+            Ok(ics(user, records))
+        }
+        _ => Err("usage: export <csv|jsonl> [--users a,b] [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--period day|week|month|all]\n       export ics <user>".to_string()),
+    }
+}
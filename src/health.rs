@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{Config, Metrics, Storage, discord_connected};
+
+#[derive(Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Named checks reported by `/healthz` and `/readyz`.
+#[derive(Serialize)]
+pub struct Health {
+    pub ok: bool,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Health {
+    /// Whether the collector is still running and able to save.
+    pub fn live() -> Self {
+        Self::new([
+            ("fetch_loop", Self::fetch_loop()),
+            ("storage", Self::storage()),
+        ])
+    }
+
+    /// Whether the served data is fresh and every integration is up.
+    pub fn ready() -> Self {
+        Self::new([
+            ("fetch_loop", Self::fetch_loop()),
+            ("fetch_success", Self::fetch_success()),
+            ("storage", Self::storage()),
+            ("discord", Self::discord()),
+        ])
+    }
+
+    fn new<const N: usize>(checks: [(&'static str, Check); N]) -> Self {
+        Self {
+            ok: checks.iter().all(|(_, check)| check.ok),
+            checks: checks.into_iter().collect(),
+        }
+    }
+
+    // how old a fetch can be before it is considered stalled, in seconds
+    fn max_age() -> u64 {
+        let config = Config::get();
+        config.interval * config.health_intervals.max(1)
+    }
+
+    fn fetch_loop() -> Check {
+        let age =
+            (chrono::Utc::now().timestamp() as u64).saturating_sub(Storage::copy().last_fetch);
+        Check {
+            ok: age <= Self::max_age(),
+            detail: format!("last fetch started {age}s ago"),
+        }
+    }
+
+    fn fetch_success() -> Check {
+        let now = chrono::Utc::now().timestamp() as u64;
+        let users = &Config::get().users;
+        let fresh = users
+            .iter()
+            .filter(|user| {
+                Metrics::get()
+                    .last_success(&user.name)
+                    .is_some_and(|time| now.saturating_sub(time) <= Self::max_age())
+            })
+            .count();
+
+        Check {
+            ok: fresh * 2 > users.len(),
+            detail: format!(
+                "{fresh}/{} users fetched in the last {}s",
+                users.len(),
+                Self::max_age()
+            ),
+        }
+    }
+
+    fn storage() -> Check {
+        let path = PathBuf::from(env::var("CONFIG").expect("missing ENV `CONFIG`"))
+            .join("storage.json.check");
+
+        // saves write a temporary file and rename it, which needs the folder to be writable
+        match fs::write(&path, b"").and_then(|_| fs::remove_file(&path)) {
+            Ok(_) => Check {
+                ok: true,
+                detail: "writable".to_string(),
+            },
+            Err(e) => Check {
+                ok: false,
+                detail: e.to_string(),
+            },
+        }
+    }
+
+    fn discord() -> Check {
+        match discord_connected() {
+            None => Check {
+                ok: true,
+                detail: "disabled".to_string(),
+            },
+            Some(true) => Check {
+                ok: true,
+                detail: "connected".to_string(),
+            },
+            Some(false) => Check {
+                ok: false,
+                detail: "disconnected".to_string(),
+            },
+        }
+    }
+}
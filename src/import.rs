@@ -0,0 +1,346 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::{Config, Record, Storage};
+
+/// A session from another source, in unix millis.
+pub struct Imported {
+    pub user: String,
+    pub beginning: u64,
+    // `None` if the session was ongoing when it was exported
+    pub end: Option<u64>,
+}
+
+// position in the input, and the session or why it is invalid
+type Row = (String, Result<Imported, String>);
+
+#[derive(Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    // still ongoing when exported, the tracker records these itself
+    pub ongoing: usize,
+    // older than `expire`, would be deleted on the next fetch
+    pub expired: usize,
+    // overlapping or after the latest tracked login
+    pub conflicts: usize,
+    pub records_before: usize,
+    pub records_after: usize,
+}
+
+impl Imported {
+    /// Parses CSV with a `user,start,end` header, JSON Lines or a JSON array.
+    ///
+    /// Times can be unix millis or RFC 3339, as written by `export`. Invalid rows are left out
+    /// and returned with why they are invalid, the whole input only fails if it cannot be read.
+    pub fn parse(content: &str) -> Result<(Vec<Self>, Vec<String>), Vec<String>> {
+        let trimmed = content.trim_start();
+        let rows = if trimmed.starts_with('[') {
+            match serde_json::from_str::<Vec<Value>>(trimmed) {
+                Ok(values) => values
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| (format!("entry {}", index + 1), Self::from_json(value)))
+                    .collect(),
+                Err(e) => return Err(vec![e.to_string()]),
+            }
+        } else if trimmed.starts_with('{') {
+            content
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| {
+                    (
+                        format!("line {}", index + 1),
+                        serde_json::from_str(line)
+                            .map_err(|e| e.to_string())
+                            .and_then(|value| Self::from_json(&value)),
+                    )
+                })
+                .collect()
+        } else {
+            Self::from_csv(content)?
+        };
+
+        let mut sessions = Vec::new();
+        let mut errors = Vec::new();
+        for (position, row) in rows {
+            match row.and_then(Self::validate) {
+                Ok(session) => sessions.push(session),
+                Err(e) => errors.push(format!("{position}: {e}")),
+            }
+        }
+
+        Ok((sessions, errors))
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let time = |names: &[&str]| {
+            let Some(value) = names
+                .iter()
+                .find_map(|name| value.get(name).filter(|value| !value.is_null()))
+            else {
+                return Ok(None);
+            };
+            match value {
+                Value::Number(number) => number
+                    .as_u64()
+                    .map(Some)
+                    .ok_or(format!("invalid time `{number}`")),
+                Value::String(time) => Self::time(time).map(Some),
+                _ => Err(format!("invalid time `{value}`")),
+            }
+        };
+
+        Ok(Self {
+            user: value
+                .get("user")
+                .and_then(Value::as_str)
+                .ok_or("missing `user`")?
+                .to_string(),
+            beginning: time(&["beginning", "start"])?.ok_or("missing `beginning`")?,
+            // `export` writes a null end for ongoing sessions
+            end: time(&["end"])?,
+        })
+    }
+
+    fn from_csv(content: &str) -> Result<Vec<Row>, Vec<String>> {
+        let mut lines = content.lines().enumerate();
+        let header = Self::split_csv(lines.next().map(|(_, line)| line).unwrap_or_default());
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|column| column.trim() == name)
+                .ok_or(format!("missing `{name}` column in the header"))
+        };
+        let (user, start, end) = match (column("user"), column("start"), column("end")) {
+            (Ok(user), Ok(start), Ok(end)) => (user, start, end),
+            (user, start, end) => {
+                return Err([user, start, end]
+                    .into_iter()
+                    .filter_map(Result::err)
+                    .collect());
+            }
+        };
+
+        Ok(lines
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let fields = Self::split_csv(line);
+                let optional = |column: usize| {
+                    fields
+                        .get(column)
+                        .map(|field| field.trim())
+                        .filter(|field| !field.is_empty())
+                };
+                let field = |column: usize| {
+                    optional(column).ok_or(format!("missing `{}`", header[column].trim()))
+                };
+                (
+                    format!("line {}", index + 1),
+                    (|| {
+                        Ok(Self {
+                            user: field(user)?.to_string(),
+                            beginning: Self::time(field(start)?)?,
+                            // `export` leaves the end of ongoing sessions empty
+                            end: optional(end).map(Self::time).transpose()?,
+                        })
+                    })(),
+                )
+            })
+            .collect())
+    }
+
+    // splits a line on commas outside of double quotes
+    fn split_csv(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    fields.last_mut().unwrap().push(chars.next().unwrap());
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+
+        fields
+    }
+
+    fn time(time: &str) -> Result<u64, String> {
+        time.parse::<u64>().or_else(|_| {
+            DateTime::parse_from_rfc3339(time)
+                .map(|time| time.timestamp_millis() as u64)
+                .map_err(|_| format!("invalid time `{time}`, expected unix millis or RFC 3339"))
+        })
+    }
+
+    fn validate(self) -> Result<Self, String> {
+        if !Config::get()
+            .users
+            .iter()
+            .any(|user| user.name == self.user)
+        {
+            return Err(format!("`{}` is not a configured user", self.user));
+        }
+        let now = Utc::now().timestamp_millis() as u64;
+        if self.end.is_some_and(|end| end <= self.beginning) {
+            return Err("session ends before it begins".to_string());
+        }
+        if self.end.unwrap_or(self.beginning) > now {
+            return Err("session is in the future".to_string());
+        }
+
+        Ok(self)
+    }
+}
+
+impl Storage {
+    /// Merges finished sessions into the records of each user.
+    ///
+    /// A session overlapping or less than `merge` seconds apart from a record becomes part of
+    /// it, like consecutive logins in `push_beginning`, records already stored are left as they
+    /// are otherwise. Ongoing sessions and sessions reaching the latest tracked login are skipped
+    /// so the next fetch still recognises it.
+    pub fn import(&mut self, sessions: Vec<Imported>) -> BTreeMap<String, ImportSummary> {
+        let config = Config::get();
+        let merge = config.merge * 1000;
+        let expire =
+            (Utc::now().timestamp() as u64).saturating_sub(config.expire * 3600 * 24) * 1000;
+        let mut summaries = BTreeMap::<String, ImportSummary>::new();
+        let mut imported = BTreeMap::<String, Vec<Record>>::new();
+
+        for session in sessions {
+            let summary = summaries.entry(session.user.clone()).or_default();
+            let latest = self
+                .users
+                .get(&session.user)
+                .and_then(|records| records.last())
+                .map(|record| record.session_begin);
+
+            let Some(end) = session.end else {
+                summary.ongoing += 1;
+                continue;
+            };
+
+            if end < expire {
+                summary.expired += 1;
+            } else if latest.is_some_and(|latest| end >= latest) {
+                summary.conflicts += 1;
+            } else {
+                summary.imported += 1;
+                imported.entry(session.user).or_default().push(Record {
+                    beginning: session.beginning,
+                    session_begin: session.beginning,
+                    end: Some(end),
+                });
+            }
+        }
+
+        for (user, mut records) in imported {
+            let existing = self.users.entry(user.clone()).or_default();
+            let summary = summaries.get_mut(&user).unwrap();
+            summary.records_before = existing.len();
+
+            records.sort_by_key(|record| record.beginning);
+
+            for mut record in records {
+                // records within `merge` of the session, contiguous as records are sorted
+                let touching = existing
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| {
+                        other.beginning <= record.end.unwrap() + merge
+                            && other.end.is_none_or(|end| record.beginning <= end + merge)
+                    })
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+
+                let (Some(&first), Some(&last)) = (touching.first(), touching.last()) else {
+                    let index =
+                        existing.partition_point(|other| other.beginning < record.beginning);
+                    existing.insert(index, record);
+                    continue;
+                };
+
+                for other in &existing[first..=last] {
+                    record.beginning = record.beginning.min(other.beginning);
+                    record.end = record.end.zip(other.end).map(|(a, b)| a.max(b));
+                    record.session_begin = record.session_begin.max(other.session_begin);
+                }
+                existing.splice(first..=last, [record]);
+            }
+
+            summary.records_after = existing.len();
+            self.first_fetch = self.first_fetch.min(
+                existing
+                    .first()
+                    .map_or(u64::MAX, |record| record.beginning / 1000),
+            );
+        }
+
+        for (user, summary) in summaries.iter_mut() {
+            if summary.imported == 0 {
+                summary.records_before = self.users.get(user).map_or(0, Vec::len);
+                summary.records_after = summary.records_before;
+            }
+        }
+
+        summaries
+    }
+}
+
+/// `import <file|-> [--dry-run]`, merges sessions into `storage.json`.
+pub fn import_command(args: &[String]) -> Result<String, String> {
+    let usage = "usage: import <file|-> [--dry-run]";
+    let (path, dry_run) = match args {
+        [path] => (path, false),
+        [path, flag] | [flag, path] if flag == "--dry-run" => (path, true),
+        _ => return Err(usage.to_string()),
+    };
+
+    let content = if path == "-" {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| e.to_string())?;
+        content
+    } else {
+        fs::read_to_string(path).map_err(|e| format!("cannot read `{path}` - {e}"))?
+    };
+
+    let (sessions, invalid) = Imported::parse(&content).map_err(|errors| errors.join("\n"))?;
+    let mut storage = Storage::init();
+    let summaries = storage.import(sessions);
+
+    if !dry_run {
+        storage.save();
+    }
+
+    Ok(invalid
+        .iter()
+        .map(|e| format!("Skipped {e}\n"))
+        .chain(summaries.iter().map(|(user, summary)| {
+            format!(
+                "{user}: {} imported, {} ongoing, {} expired, {} conflicting with tracked sessions, {} -> {} records\n",
+                summary.imported,
+                summary.ongoing,
+                summary.expired,
+                summary.conflicts,
+                summary.records_before,
+                summary.records_after
+            )
+        }))
+        .chain((!invalid.is_empty()).then(|| format!("{} invalid rows skipped\n", invalid.len())))
+        .chain(dry_run.then(|| "Dry run, nothing was saved\n".to_string()))
+        .collect())
+}
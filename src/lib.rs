@@ -1,7 +1,7 @@
 #![allow(static_mut_refs)]
 
 mod config;
-pub use config::Config;
+pub use config::*;
 
 mod storage;
 pub use storage::*;
@@ -14,5 +14,56 @@ pub use web::run;
 mod mermaid;
 pub use mermaid::*;
 
+mod compare;
+pub use compare::*;
+
+mod overlap;
+pub use overlap::*;
+
+mod calendar;
+pub use calendar::*;
+
 mod discord;
 pub use discord::*;
+
+mod notifier;
+pub use notifier::*;
+
+mod outbox;
+pub use outbox::*;
+
+mod commands;
+
+mod template;
+pub use template::*;
+
+mod digest;
+
+mod limits;
+pub use limits::*;
+
+mod metrics;
+pub use metrics::*;
+
+mod health;
+pub use health::*;
+
+mod export;
+pub use export::*;
+
+mod import;
+pub use import::*;
+
+mod logging;
+pub use logging::*;
+
+pub mod shutdown;
+pub use shutdown::Phase;
+
+mod auth;
+pub use auth::*;
+
+mod admin;
+
+mod assets;
+pub use assets::*;
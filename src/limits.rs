@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, Utc};
+
+use crate::{BroadcastEvent, Config, Limits, Storage, Window};
+
+#[derive(Clone, Copy)]
+pub enum LimitKind {
+    Daily,
+    Weekly,
+    Session,
+    Curfew,
+}
+
+/// Current usage towards one limit, in seconds.
+pub struct Usage {
+    pub kind: LimitKind,
+    pub used: u64,
+    pub max: u64,
+    // alerts are sent at most once per key
+    pub key: String,
+}
+
+impl LimitKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Session => "session",
+            Self::Curfew => "curfew",
+        }
+    }
+}
+
+impl Usage {
+    // how much of the limit is used, for progress bars
+    pub fn percent(&self) -> f32 {
+        (self.used as f32 / self.max.max(1) as f32 * 100.).min(100.)
+    }
+
+    pub fn level(&self, warn: f32) -> Option<&'static str> {
+        match self.kind {
+            _ if self.used >= self.max => Some("exceeded"),
+            LimitKind::Curfew if self.used > 0 => Some("approaching"),
+            LimitKind::Curfew => None,
+            _ if self.used as f32 >= self.max as f32 * warn => Some("approaching"),
+            _ => None,
+        }
+    }
+}
+
+impl Window {
+    fn parse(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap_or(NaiveTime::MIN)
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        let (from, to) = (Self::parse(&self.from), Self::parse(&self.to));
+        if from <= to {
+            from <= time && time < to
+        } else {
+            from <= time || time < to
+        }
+    }
+
+    /// Seconds until the window closes, zero if outside the window.
+    pub fn remaining(&self, now: DateTime<Utc>) -> u64 {
+        if !self.contains(now.time()) {
+            return 0;
+        }
+
+        let to = now.with_time(Self::parse(&self.to)).unwrap();
+        let to = if to <= now { to + Days::new(1) } else { to };
+        (to - now).num_seconds() as u64
+    }
+}
+
+impl Limits {
+    pub fn usage(&self, user: &str, storage: &Storage) -> Vec<Usage> {
+        let now = Utc::now();
+        let midnight = now.with_time(NaiveTime::MIN).unwrap();
+        let monday = midnight - Days::new(now.weekday().num_days_from_monday() as u64);
+        let hours = |hours: f32| (hours * 3600.) as u64;
+        let session = storage
+            .users
+            .get(user)
+            .and_then(|records| records.last())
+            .filter(|record| record.end.is_none());
+
+        let mut usage = Vec::new();
+
+        if let Some(daily) = self.daily {
+            usage.push(Usage {
+                kind: LimitKind::Daily,
+                used: storage.playtime(user, midnight.timestamp_millis() as u64),
+                max: hours(daily),
+                key: midnight.date_naive().to_string(),
+            });
+        }
+
+        if let Some(weekly) = self.weekly {
+            usage.push(Usage {
+                kind: LimitKind::Weekly,
+                used: storage.playtime(user, monday.timestamp_millis() as u64),
+                max: hours(weekly),
+                key: monday.date_naive().to_string(),
+            });
+        }
+
+        if let Some(max) = self.session {
+            usage.push(Usage {
+                kind: LimitKind::Session,
+                used: session
+                    .map(|record| {
+                        (now.timestamp_millis() as u64).saturating_sub(record.beginning) / 1000
+                    })
+                    .unwrap_or(0),
+                max: hours(max),
+                key: session
+                    .map(|record| record.beginning.to_string())
+                    .unwrap_or_default(),
+            });
+        }
+
+        // online outside the window exceeds the limit, the last 15 minutes inside it warn
+        if let Some(allowed) = &self.allowed
+            && let Some(record) = session
+        {
+            let remaining = allowed.remaining(now);
+            usage.push(Usage {
+                kind: LimitKind::Curfew,
+                used: 900u64.saturating_sub(remaining),
+                max: 900,
+                key: format!("{}-{}", now.date_naive(), record.beginning),
+            });
+        }
+
+        usage
+    }
+}
+
+impl Storage {
+    /// Broadcasts limits that have been approached or exceeded since the last check.
+    pub fn check_limits(&mut self) {
+        for (user, limits) in Config::get().limits.iter() {
+            let usage = limits.usage(user, self);
+            let alerts = self.alerts.entry(user.clone()).or_default();
+
+            // forget alerts from previous days, weeks and sessions
+            let current = usage
+                .iter()
+                .map(|usage| format!("{}:{}", usage.kind.name(), usage.key))
+                .collect::<HashSet<_>>();
+            alerts.retain(|alert| {
+                current.contains(alert.rsplit_once(':').map(|(key, _)| key).unwrap_or(""))
+            });
+
+            for usage in usage {
+                let Some(level) = usage.level(limits.warn) else {
+                    continue;
+                };
+
+                let key = format!("{}:{}", usage.kind.name(), usage.key);
+                if !alerts.insert(format!("{key}:{level}")) {
+                    continue;
+                }
+                // exceeding a limit implies approaching it
+                alerts.insert(format!("{key}:approaching"));
+
+                BroadcastEvent::Limit {
+                    user: user.clone(),
+                    kind: usage.kind,
+                    exceeded: level == "exceeded",
+                    used: usage.used,
+                    max: usage.max,
+                }
+                .broadcast();
+            }
+        }
+    }
+}
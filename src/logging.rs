@@ -0,0 +1,104 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use tracing_subscriber::EnvFilter;
+
+use crate::{Config, NotifierConfig};
+
+/// Api keys, the discord and admin tokens, webhook urls and ntfy topics, longest first.
+fn secrets() -> Arc<Vec<String>> {
+    static SECRETS: Mutex<Option<(usize, Arc<Vec<String>>)>> = Mutex::new(None);
+
+    // recomputed whenever the config is replaced, as keys can be added from the admin page
+    let config = Config::get();
+    let mut cached = SECRETS.lock().unwrap();
+    if let Some((address, secrets)) = cached.as_ref()
+        && *address == config as *const Config as usize
+    {
+        return secrets.clone();
+    }
+
+    let secrets = {
+        let mut secrets = config
+            .keys
+            .iter()
+            .chain([&config.discord_token])
+            .chain(&config.admin_token)
+            .chain(
+                config
+                    .notifiers
+                    .iter()
+                    .filter_map(|notifier| match notifier {
+                        NotifierConfig::DiscordWebhook { url, .. }
+                        | NotifierConfig::Webhook { url, .. } => Some(url),
+                        // anyone who knows the topic can subscribe to it
+                        NotifierConfig::Ntfy { topic, .. } => Some(topic),
+                        NotifierConfig::Stdout { .. } => None,
+                    }),
+            )
+            // anything shorter is too common to replace and too easy to guess anyway
+            .filter(|secret| secret.len() >= 8)
+            .cloned()
+            .collect::<Vec<_>>();
+        // a key could be part of a longer secret
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        Arc::new(secrets)
+    };
+    *cached = Some((config as *const Config as usize, secrets.clone()));
+    secrets
+}
+
+/// Replaces every configured secret in `text`.
+pub fn redact(text: &str) -> String {
+    secrets().iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret, "[redacted]")
+    })
+}
+
+/// Last 4 characters of an api key, enough to tell keys apart.
+pub fn key_hint(key: &str) -> String {
+    let chars = key.chars().collect::<Vec<_>>();
+    format!(
+        "...{}",
+        chars[chars.len().saturating_sub(4)..]
+            .iter()
+            .collect::<String>()
+    )
+}
+
+// each log line arrives in one write, so secrets are never split
+struct RedactingStdout;
+
+impl Write for RedactingStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Logs to stdout at the configured level, `RUST_LOG` takes precedence.
+pub fn init_logging() {
+    let config = &Config::get().log;
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level)),
+        )
+        .with_writer(|| RedactingStdout);
+
+    if config.json {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+
+    // loaded anyway, a user that cannot be edited is better than not starting
+    if let Err(e) = Config::get().validate(None) {
+        tracing::warn!(error = e, "Invalid master.json");
+    }
+}
@@ -1,6 +1,31 @@
-use hypixel_screentime::{run, start_discord, tasks};
+use std::{env, process};
+
+use hypixel_screentime::{
+    export_command, import_command, init_logging, run, shutdown, start_discord, start_notifiers,
+    stop_discord, tasks,
+};
 
 #[tokio::main]
 async fn main() {
-    tokio::join!(tasks::fetch(), run(), start_discord());
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let command = match args.first().map(String::as_str) {
+        Some("export") => export_command,
+        Some("import") => import_command,
+        _ => {
+            init_logging();
+            tokio::spawn(shutdown::listen_for_signals());
+            tokio::join!(tasks::fetch(), run(), start_discord(), start_notifiers());
+            stop_discord().await;
+            return;
+        }
+    };
+
+    match command(&args[1..]) {
+        Ok(output) => print!("{output}"),
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
 }
@@ -1,14 +1,18 @@
 use std::{collections::HashMap, fmt::Display, sync::OnceLock};
 
-use chrono::{DateTime, Datelike, NaiveTime, Timelike, Utc};
+use askama::Template;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Serialize;
 
-use crate::{Record, Storage, web::HEART};
+use crate::{Calendar, Config, LimitKind, Limits, Overlap, Record, Storage, Usage};
 
 #[derive(Clone)]
 pub struct Mermaid {
-    weekly: Weekly,
-    daily: Daily,
-    timeofday: TimeOfDay,
+    pub weekly: Weekly,
+    pub daily: Daily,
+    pub timeofday: TimeOfDay,
+    pub calendar: Calendar,
+    pub sessions: Sessions,
 }
 
 // reversed order
@@ -18,108 +22,84 @@ pub struct Daily(pub [u64; 7]);
 pub struct Weekly(pub [u64; 10]);
 #[derive(Clone)]
 pub struct TimeOfDay(pub [u64; 24]);
+/// Lengths of completed sessions in seconds, shortest first.
+#[derive(Clone)]
+pub struct Sessions(pub Vec<u64>);
+
+#[derive(Serialize)]
+pub struct SessionStats {
+    pub count: usize,
+    pub mean: u64,
+    pub median: u64,
+    pub p90: u64,
+    pub longest: u64,
+}
+
+// upper bounds of each histogram bucket in minutes, the last bucket is unbounded
+const SESSION_BUCKETS: [u64; 7] = [15, 30, 60, 120, 180, 240, 360];
+
+pub fn format_duration(seconds: u64) -> String {
+    format!(
+        "{:0>2}:{:0>2}:{:0>2}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
 
 pub static mut MERMAID_DATA: OnceLock<HashMap<String, OnceLock<Mermaid>>> = OnceLock::new();
 
+#[derive(Template)]
+#[template(path = "user.html")]
+struct UserPage<'a> {
+    user: &'a str,
+    online: bool,
+    // up to 3 users and seconds spent online together
+    online_with: Vec<(String, u64)>,
+    limits: Option<&'static Limits>,
+    usage: Vec<Usage>,
+    mermaid: Mermaid,
+    stats: SessionStats,
+    records: Vec<(String, String)>,
+}
+
 impl Mermaid {
-    pub fn html(username: &str) -> String {
-        if Storage::copy().users.contains_key(username) {
-            let user = Mermaid::get(username);
-            format!(
-                r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-  <meta charset="utf-8">
-  <title>{username} - Hypixel Screentime</title>
-</head>
-<body>
-<script type="module">
-  import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@11/dist/mermaid.esm.min.mjs';
-  mermaid.initialize({{ startOnLoad: true, theme: "dark" }});
-</script>
-<style>
-body {{
-    background: #333333;
-    text-align: center;
-    color: #eceff4;
-    font-family: Arial, Helvetica, sans-serif;
-}}
-
-.green {{
-    color: #a6e3a1 !important;
-}}
-
-table {{
-    margin: auto;
-}}
-
-table * * {{
-    padding: 10px;
-}}
-
-#home {{
-    text-decoration: none;
-    padding: 20px;
-    position: absolute;
-    top: 0px;
-    left: 0px;
-}}
-
-#home {{
-    color: #eceff4;
-    transition: 100ms;
-}}
-
-#home:hover {{
-    transition: 100ms;
-    color: #ee99a0;
-}}
-</style>
-<a id="home" href="/">&gt;Back Home</a>
-<h1{}>{username}{}</h1>
-<pre class="mermaid">
-  {}
-</pre>
-<pre class="mermaid">
-  {}
-</pre>
-<pre class="mermaid">
-  {}
-</pre>
-
-<h2>Login Records</h2>
-<table>
-<tr>
-  <th>Login</th>
-  <th>Logout</th>
-</tr>
-{}
-</table>
-<br>
-<br>
-<footer>
-  <p>Hypixel Screentime by <i><b>Sirius</b></i> | <span style="border-bottom: 2px solid #a6e3a1;"><a class="green" target="_blank" href="https://github.com/siriusmart/hypixel-screentime" style="text-decoration: none;">Written with {HEART} in Rust</a></span></p>
-</footer>
-</body>
-</html>"#,
-                if Storage::is_online(username) {
-                    " class=\"green\""
-                } else {
-                    ""
-                },
-                if Storage::is_online(username) {
-                    " (online)"
-                } else {
-                    ""
-                },
-                user.timeofday,
-                user.daily,
-                user.weekly,
-                Storage::print_log(username)
-            )
-        } else {
-            "No such user".to_string()
+    /// Page of `username`, `None` if nothing was recorded for them.
+    pub fn html(username: &str) -> Option<String> {
+        if !Storage::copy().users.contains_key(username) {
+            return None;
         }
+
+        let mermaid = Mermaid::get(username);
+        let limits = Config::get().limits.get(username);
+        Some(
+            UserPage {
+                user: username,
+                online: Storage::is_online(username),
+                online_with: Self::online_with(username),
+                limits,
+                usage: limits
+                    .map(|limits| limits.usage(username, &Storage::copy()))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|usage| !matches!(usage.kind, LimitKind::Curfew))
+                    .collect(),
+                stats: mermaid.sessions.stats(),
+                mermaid,
+                records: Storage::login_records(username),
+            }
+            .render()
+            .unwrap(),
+        )
+    }
+
+    fn online_with(username: &str) -> Vec<(String, u64)> {
+        let from = (chrono::Utc::now().timestamp() as u64)
+            .saturating_sub(Config::get().expire * 3600 * 24)
+            * 1000;
+        let mut others = Overlap::online_with(username, from);
+        others.truncate(3);
+        others
     }
 
     pub fn get(username: &str) -> Self {
@@ -147,7 +127,12 @@ table * * {{
     }
 
     pub fn update() {
-        unsafe { *MERMAID_DATA.get_mut().unwrap() = HashMap::new() }
+        // nothing is cached until the first page is viewed
+        unsafe {
+            if let Some(data) = MERMAID_DATA.get_mut() {
+                *data = HashMap::new()
+            }
+        }
     }
 
     pub fn init() -> HashMap<String, Mermaid> {
@@ -193,53 +178,118 @@ table * * {{
                 }
             };
 
-            let start = chrono::DateTime::from_timestamp_millis(record.beginning as i64).unwrap();
-            let end = chrono::DateTime::from_timestamp_millis(
-                record.end.unwrap_or(now.timestamp_millis() as u64) as i64,
-            )
-            .unwrap();
-
-            if start.date_naive() == end.date_naive() {
-                push(start, end);
-            } else {
-                let start_roundup = start
-                    .with_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap())
-                    .unwrap()
-                    + chrono::Duration::days(1);
-                let end_rounddown = end.with_minute(0).unwrap().with_second(0).unwrap();
-
-                push(start, start_roundup);
-                push(end_rounddown, end);
-
-                for day in 0..end_rounddown.num_days_from_ce() - start_roundup.num_days_from_ce() {
-                    push(
-                        start_roundup + chrono::Duration::days(day as i64),
-                        start_roundup + chrono::Duration::days(day as i64 + 1),
-                    )
-                }
-            }
+            record
+                .split_days(now)
+                .into_iter()
+                .for_each(|(start, end)| push(start, end));
         });
 
         Self {
             weekly: Weekly(weekly),
             daily: Daily(daily),
             timeofday: TimeOfDay(timeofday),
+            calendar: Calendar::build(records),
+            sessions: Sessions::build(records),
         }
     }
 }
 
-impl Display for Weekly {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Weekly {
+    pub fn averages(&self) -> Vec<f32> {
         let first_fetch =
             chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0).unwrap();
-        let weekly = self
-            .0
+        self.0
             .iter()
             .rev()
             .map(|time| {
                 *time as f32 / ((chrono::Utc::now() - first_fetch).num_weeks() + 1) as f32 / 3600.
             })
+            .collect()
+    }
+}
+
+impl TimeOfDay {
+    pub fn averages(&self) -> Vec<f32> {
+        let first_fetch =
+            chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0).unwrap();
+        self.0
+            .iter()
+            .map(|time| {
+                *time as f32 / ((chrono::Utc::now() - first_fetch).num_days() + 1) as f32 / 60.
+            })
+            .collect()
+    }
+}
+
+impl Daily {
+    pub fn averages(&self) -> Vec<f32> {
+        let first_fetch =
+            chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0).unwrap();
+        self.0
+            .iter()
+            .map(|time| {
+                *time as f32 / ((chrono::Utc::now() - first_fetch).num_weeks() + 1) as f32 / 3600.
+            })
+            .collect()
+    }
+}
+
+impl Sessions {
+    pub fn build(records: &[Record]) -> Self {
+        let mut lengths = records
+            .iter()
+            .filter_map(|record| Some(record.end?.saturating_sub(record.beginning) / 1000))
             .collect::<Vec<_>>();
+        lengths.sort_unstable();
+        Self(lengths)
+    }
+
+    fn percentile(&self, p: f32) -> u64 {
+        if self.0.is_empty() {
+            return 0;
+        }
+
+        self.0[((self.0.len() as f32 * p).ceil() as usize).clamp(1, self.0.len()) - 1]
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        SessionStats {
+            count: self.0.len(),
+            mean: self.0.iter().sum::<u64>() / self.0.len().max(1) as u64,
+            median: self.percentile(0.5),
+            p90: self.percentile(0.9),
+            longest: self.0.last().copied().unwrap_or(0),
+        }
+    }
+
+    pub fn histogram(&self) -> [usize; SESSION_BUCKETS.len() + 1] {
+        let mut histogram = [0; SESSION_BUCKETS.len() + 1];
+        self.0.iter().for_each(|length| {
+            histogram[SESSION_BUCKETS
+                .iter()
+                .position(|bound| *length < bound * 60)
+                .unwrap_or(SESSION_BUCKETS.len())] += 1;
+        });
+        histogram
+    }
+}
+
+impl Display for Sessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            r#"xychart-beta
+     title "Session length"
+     x-axis ["<15m", "15-30m", "30m-1h", "1-2h", "2-3h", "3-4h", "4-6h", "6h+"]
+     y-axis "Sessions"
+     bar {}
+"#,
+            serde_json::to_string(&self.histogram()).unwrap()
+        ))
+    }
+}
+
+impl Display for Weekly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
             r#"xychart-beta
      title "Weekly login"
@@ -247,42 +297,24 @@ impl Display for Weekly {
      y-axis "Avg. hours online"
      bar {}
 "#,
-            serde_json::to_string(&weekly).unwrap()
+            serde_json::to_string(&self.averages()).unwrap()
         ))
     }
 }
 
 impl Display for TimeOfDay {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let first_fetch =
-            chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0).unwrap();
-        let timeofday = self
-            .0
-            .iter()
-            .map(|time| {
-                *time as f32 / ((chrono::Utc::now() - first_fetch).num_days() + 1) as f32 / 60.
-            })
-            .collect::<Vec<_>>();
         f.write_fmt(format_args!(r#"xychart-beta
     title "Time of day (GMT)"
     x-axis [00, 01, 02, 03, 04, 05, 06, 07, 08, 09, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23]
     y-axis "Avg. minutes online"
     bar {}
-"#, serde_json::to_string(&timeofday).unwrap()))
+"#, serde_json::to_string(&self.averages()).unwrap()))
     }
 }
 
 impl Display for Daily {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let first_fetch =
-            chrono::DateTime::from_timestamp(Storage::copy().first_fetch as i64, 0).unwrap();
-        let daily = self
-            .0
-            .iter()
-            .map(|time| {
-                *time as f32 / ((chrono::Utc::now() - first_fetch).num_weeks() + 1) as f32 / 3600.
-            })
-            .collect::<Vec<_>>();
         f.write_fmt(format_args!(
             r#"xychart-beta
      title "Day of week"
@@ -290,7 +322,7 @@ impl Display for Daily {
      y-axis "Avg. hours online"
      bar {}
 "#,
-            serde_json::to_string(&daily).unwrap()
+            serde_json::to_string(&self.averages()).unwrap()
         ))
     }
 }
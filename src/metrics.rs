@@ -0,0 +1,252 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::Write,
+    sync::{Mutex, OnceLock, atomic::Ordering},
+    time::Duration,
+};
+
+use crate::{Access, Config, Outbox, Storage, key_hint};
+
+// upper bounds of the fetch duration histogram, in seconds
+const BUCKETS: [f64; 8] = [0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30.];
+
+/// Collector counters exported at `/metrics`, reset on restart.
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    fetch_buckets: [u64; BUCKETS.len()],
+    fetch_count: u64,
+    fetch_sum: f64,
+    errors: BTreeMap<&'static str, u64>,
+    // redacted key to requests made with it
+    requests: BTreeMap<String, u64>,
+    // username to seconds online observed since start
+    playtime: BTreeMap<String, u64>,
+    // username to when it was last fetched successfully, in unix seconds
+    last_success: HashMap<String, u64>,
+}
+
+impl Metrics {
+    pub fn get() -> &'static Self {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+        METRICS.get_or_init(Self::default)
+    }
+
+    pub fn request(&self, key: &str) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .requests
+            .entry(key_hint(key))
+            .or_default() += 1;
+    }
+
+    pub fn error(&self, e: &(dyn Error + 'static)) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .errors
+            .entry(Self::kind(e))
+            .or_default() += 1;
+    }
+
+    pub fn success(&self, user: &str, time: u64) {
+        self.inner
+            .lock()
+            .unwrap()
+            .last_success
+            .insert(user.to_string(), time);
+    }
+
+    pub fn fetched(&self, took: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let secs = took.as_secs_f64();
+
+        inner.fetch_count += 1;
+        inner.fetch_sum += secs;
+        for (bucket, count) in BUCKETS.iter().zip(inner.fetch_buckets.iter_mut()) {
+            if secs <= *bucket {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn played(&self, user: &str, secs: u64) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .playtime
+            .entry(user.to_string())
+            .or_default() += secs;
+    }
+
+    /// When `user` was last fetched successfully, in unix seconds.
+    pub fn last_success(&self, user: &str) -> Option<u64> {
+        self.inner.lock().unwrap().last_success.get(user).copied()
+    }
+
+    fn kind(e: &(dyn Error + 'static)) -> &'static str {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout() {
+                "timeout"
+            } else if e.is_status() {
+                "status"
+            } else if e.is_decode() {
+                "decode"
+            } else {
+                "request"
+            }
+        } else if e.is::<serde_json::Error>() {
+            "decode"
+        } else {
+            "other"
+        }
+    }
+
+    /// Renders every metric in the prometheus text format, with the users `access` can list.
+    pub fn render(&self, access: &Access) -> String {
+        let inner = self.inner.lock().unwrap();
+        let delivery = Outbox::metrics();
+        let mut out = String::new();
+
+        metric(
+            &mut out,
+            "online",
+            "gauge",
+            "Whether the user is currently online.",
+            Config::get()
+                .users
+                .iter()
+                .filter(|user| access.can_list(&user.name))
+                .map(|user| {
+                    (
+                        label("user", &user.name),
+                        (Storage::is_online(&user.name) as u8).to_string(),
+                    )
+                })
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "playtime_seconds_total",
+            "counter",
+            "Seconds the user was seen online since the collector started.",
+            inner
+                .playtime
+                .iter()
+                .filter(|(user, _)| access.can_list(user))
+                .map(|(user, secs)| (label("user", user), secs.to_string()))
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "fetch_duration_seconds",
+            "histogram",
+            "Time taken to fetch every user from the Hypixel API.",
+            BUCKETS
+                .iter()
+                .map(ToString::to_string)
+                .chain(["+Inf".to_string()])
+                .zip(inner.fetch_buckets.into_iter().chain([inner.fetch_count]))
+                .map(|(bucket, count)| {
+                    (
+                        format!("_bucket{}", label("le", &bucket)),
+                        count.to_string(),
+                    )
+                })
+                .chain([
+                    ("_sum".to_string(), inner.fetch_sum.to_string()),
+                    ("_count".to_string(), inner.fetch_count.to_string()),
+                ])
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "fetch_errors_total",
+            "counter",
+            "Failed user fetches by kind of error.",
+            inner
+                .errors
+                .iter()
+                .map(|(kind, count)| (label("kind", kind), count.to_string()))
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "api_requests_total",
+            "counter",
+            "Requests made to the Hypixel API with each key.",
+            inner
+                .requests
+                .iter()
+                .map(|(key, count)| (label("key", key), count.to_string()))
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "last_success_timestamp_seconds",
+            "gauge",
+            "When any user was last fetched successfully.",
+            vec![(
+                String::new(),
+                inner
+                    .last_success
+                    .values()
+                    .max()
+                    .copied()
+                    .unwrap_or(0)
+                    .to_string(),
+            )],
+        );
+        metric(
+            &mut out,
+            "discord_messages_total",
+            "counter",
+            "Discord messages by delivery outcome.",
+            [
+                ("sent", &delivery.sent),
+                ("failed", &delivery.failed),
+                ("dropped", &delivery.dropped),
+                ("coalesced", &delivery.coalesced),
+            ]
+            .into_iter()
+            .map(|(outcome, count)| {
+                (
+                    label("outcome", outcome),
+                    count.load(Ordering::Relaxed).to_string(),
+                )
+            })
+            .collect(),
+        );
+
+        out
+    }
+}
+
+// each sample is a name suffix with labels, and a value
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: Vec<(String, String)>) {
+    let _ = writeln!(out, "# HELP hypixel_screentime_{name} {help}");
+    let _ = writeln!(out, "# TYPE hypixel_screentime_{name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "hypixel_screentime_{name}{labels} {value}");
+    }
+}
+
+fn label(name: &str, value: &str) -> String {
+    format!(
+        "{{{name}=\"{}\"}}",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
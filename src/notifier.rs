@@ -0,0 +1,358 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use futures::future::{BoxFuture, join_all};
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    Colours, Config, DiscordBot, LimitKind, NotifierConfig, Phase, Placeholders, Storage,
+    Templates, shutdown, web,
+};
+
+pub static SENDER: OnceLock<mpsc::UnboundedSender<BroadcastEvent>> = OnceLock::new();
+
+#[derive(Clone)]
+pub enum BroadcastEvent {
+    Online {
+        user: String,
+        resumed: bool,
+    },
+    Offline {
+        user: String,
+        duration: Duration,
+    },
+    // `used` and `max` are in seconds
+    Limit {
+        user: String,
+        kind: LimitKind,
+        exceeded: bool,
+        used: u64,
+        max: u64,
+    },
+}
+
+impl BroadcastEvent {
+    pub fn user(&self) -> &str {
+        match self {
+            Self::Online { user, .. } | Self::Offline { user, .. } | Self::Limit { user, .. } => {
+                user
+            }
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Online { resumed: false, .. } => "online",
+            Self::Online { resumed: true, .. } => "resumed",
+            Self::Offline { .. } => "offline",
+            Self::Limit { .. } => "limit",
+        }
+    }
+
+    /// Sends the event to live web pages and every notifier.
+    pub fn broadcast(self) {
+        tracing::info!(user = self.user(), kind = self.kind(), "Broadcasting event");
+        let _ = web::events().send(self.clone());
+        if let Some(sender) = SENDER.get() {
+            let _ = sender.send(self);
+        }
+    }
+}
+
+pub type NotifyResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> String;
+
+    fn notify<'a>(
+        &'a self,
+        event: &'a BroadcastEvent,
+        placeholders: &'a Placeholders,
+    ) -> BoxFuture<'a, NotifyResult>;
+}
+
+pub struct DiscordWebhook {
+    url: String,
+    users: HashSet<String>,
+    templates: &'static Templates,
+    embed: bool,
+    colours: &'static Colours,
+}
+
+pub struct Webhook {
+    url: String,
+    users: HashSet<String>,
+    templates: &'static Templates,
+}
+
+pub struct Ntfy {
+    url: String,
+    users: HashSet<String>,
+    templates: &'static Templates,
+}
+
+pub struct Stdout {
+    users: HashSet<String>,
+    templates: &'static Templates,
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+impl NotifierConfig {
+    pub fn build(&'static self) -> Box<dyn Notifier> {
+        match self {
+            Self::DiscordWebhook {
+                url,
+                users,
+                templates,
+                embed,
+                colours,
+            } => Box::new(DiscordWebhook {
+                url: url.clone(),
+                users: users.clone(),
+                templates,
+                embed: *embed,
+                colours,
+            }),
+            Self::Webhook {
+                url,
+                users,
+                templates,
+            } => Box::new(Webhook {
+                url: url.clone(),
+                users: users.clone(),
+                templates,
+            }),
+            Self::Ntfy {
+                server,
+                topic,
+                users,
+                templates,
+            } => Box::new(Ntfy {
+                url: format!("{}/{topic}", server.trim_end_matches('/')),
+                users: users.clone(),
+                templates,
+            }),
+            Self::Stdout { users, templates } => Box::new(Stdout {
+                users: users.clone(),
+                templates,
+            }),
+        }
+    }
+}
+
+/// Delivers broadcast events to the discord bot and every configured notifier.
+pub async fn start_notifiers() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    SENDER.set(tx).unwrap();
+
+    let notifiers = std::iter::once(Box::new(DiscordBot) as Box<dyn Notifier>)
+        .chain(Config::get().notifiers.iter().map(NotifierConfig::build))
+        .collect::<Vec<_>>();
+
+    let grace = Duration::from_secs(Config::get().offline_grace);
+    // offline events held back until their deadline, in case the user resumes
+    let mut held: HashMap<String, (BroadcastEvent, Instant)> = HashMap::new();
+
+    loop {
+        let expired = held
+            .iter()
+            .min_by_key(|(_, (_, deadline))| *deadline)
+            .map(|(user, (_, deadline))| (user.clone(), *deadline));
+
+        tokio::select! {
+            Some(event) = rx.recv() => receive(&notifiers, &mut held, grace, event).await,
+            _ = tokio::time::sleep_until(expired.as_ref().map_or_else(Instant::now, |(_, deadline)| *deadline)),
+                if expired.is_some() =>
+            {
+                let (event, _) = held.remove(&expired.unwrap().0).unwrap();
+                dispatch(&notifiers, &event).await;
+            }
+            // nothing is broadcast after this, send everything that is left
+            _ = shutdown::reached(Phase::Collected) => {
+                while let Ok(event) = rx.try_recv() {
+                    receive(&notifiers, &mut held, grace, event).await;
+                }
+                for (event, _) in held.into_values() {
+                    dispatch(&notifiers, &event).await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+async fn receive(
+    notifiers: &[Box<dyn Notifier>],
+    held: &mut HashMap<String, (BroadcastEvent, Instant)>,
+    grace: Duration,
+    event: BroadcastEvent,
+) {
+    match &event {
+        BroadcastEvent::Offline { user, .. } if !grace.is_zero() => {
+            held.insert(user.clone(), (event, Instant::now() + grace));
+        }
+        // the offline event was never sent, so neither is the resume
+        BroadcastEvent::Online {
+            user,
+            resumed: true,
+        } if held.remove(user).is_some() => {}
+        // a new session, the user did go offline in between
+        BroadcastEvent::Online { user, .. } => {
+            if let Some((offline, _)) = held.remove(user) {
+                dispatch(notifiers, &offline).await;
+            }
+            dispatch(notifiers, &event).await;
+        }
+        _ => dispatch(notifiers, &event).await,
+    }
+}
+
+async fn dispatch(notifiers: &[Box<dyn Notifier>], event: &BroadcastEvent) {
+    // waits for the fetch that sent the event to finish writing storage
+    let placeholders = Placeholders::new(event, &*Storage::get().lock().await);
+
+    for (notifier, result) in notifiers.iter().zip(
+        join_all(
+            notifiers
+                .iter()
+                .map(|notifier| notifier.notify(event, &placeholders)),
+        )
+        .await,
+    ) {
+        if let Err(e) = result {
+            tracing::warn!(notifier = notifier.name(), error = %e, "Failed to notify");
+        }
+    }
+}
+
+impl Notifier for DiscordWebhook {
+    fn name(&self) -> String {
+        "discord webhook".to_string()
+    }
+
+    fn notify<'a>(
+        &'a self,
+        event: &'a BroadcastEvent,
+        placeholders: &'a Placeholders,
+    ) -> BoxFuture<'a, NotifyResult> {
+        Box::pin(async move {
+            if !self.users.contains(event.user()) {
+                return Ok(());
+            }
+
+            let content = placeholders.render(self.templates.get(event));
+            let body = if self.embed {
+                serde_json::json!({
+                    "embeds": [{
+                        "author": { "name": event.user(), "icon_url": placeholders.get("avatar") },
+                        "description": content,
+                        "color": self.colours.get(event),
+                        "timestamp": chrono::Utc::now().to_rfc3339(),
+                    }]
+                })
+            } else {
+                serde_json::json!({ "content": content })
+            };
+
+            // the url contains the webhook token, keep it out of the logs
+            client()
+                .post(&self.url)
+                .json(&body)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(reqwest::Error::without_url)?;
+            Ok(())
+        })
+    }
+}
+
+impl Notifier for Webhook {
+    fn name(&self) -> String {
+        "webhook".to_string()
+    }
+
+    fn notify<'a>(
+        &'a self,
+        event: &'a BroadcastEvent,
+        placeholders: &'a Placeholders,
+    ) -> BoxFuture<'a, NotifyResult> {
+        Box::pin(async move {
+            if !self.users.contains(event.user()) {
+                return Ok(());
+            }
+
+            // the url can contain credentials, keep it out of the logs
+            client()
+                .post(&self.url)
+                .json(&serde_json::json!({
+                    "event": event.kind(),
+                    "user": event.user(),
+                    "message": placeholders.render(self.templates.get(event)),
+                    "duration": placeholders.get("duration"),
+                    "timestamp": chrono::Utc::now().timestamp(),
+                }))
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(reqwest::Error::without_url)?;
+            Ok(())
+        })
+    }
+}
+
+impl Notifier for Ntfy {
+    fn name(&self) -> String {
+        format!("ntfy {}", self.url)
+    }
+
+    fn notify<'a>(
+        &'a self,
+        event: &'a BroadcastEvent,
+        placeholders: &'a Placeholders,
+    ) -> BoxFuture<'a, NotifyResult> {
+        Box::pin(async move {
+            if !self.users.contains(event.user()) {
+                return Ok(());
+            }
+
+            client()
+                .post(&self.url)
+                .header("Title", format!("{} {}", event.user(), event.kind()))
+                .header("Tags", event.kind())
+                .body(placeholders.render(self.templates.get(event)))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+impl Notifier for Stdout {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn notify<'a>(
+        &'a self,
+        event: &'a BroadcastEvent,
+        placeholders: &'a Placeholders,
+    ) -> BoxFuture<'a, NotifyResult> {
+        Box::pin(async move {
+            if self.users.contains(event.user()) {
+                println!("{}", placeholders.render(self.templates.get(event)));
+            }
+            Ok(())
+        })
+    }
+}
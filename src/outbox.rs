@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::PathBuf,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    ChannelId, CreateEmbed, CreateEmbedAuthor, CreateMessage, Http, Timestamp, UserId,
+};
+use tokio::sync::Mutex;
+
+use crate::{Config, discord_error, write_atomic};
+
+// give up on a message after this many failed attempts
+const MAX_ATTEMPTS: u32 = 10;
+const MAX_BACKOFF: u64 = 600_000;
+
+/// Persistent queue of discord messages waiting to be delivered.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Outbox {
+    pub queue: Vec<Outgoing>,
+    // channel id to when it was last sent to, in unix millis
+    #[serde(skip)]
+    last_sent: HashMap<u64, u64>,
+    // messages taken from the queue and not yet sent or put back
+    #[serde(skip)]
+    sending: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Outgoing {
+    pub channel: u64,
+    // `channel` is a user id to send a direct message to
+    #[serde(default)]
+    pub direct: bool,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embed: Option<Embed>,
+    // the event this message is about, used to coalesce flaps
+    pub user: String,
+    pub kind: String,
+    pub created: u64,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub next_attempt: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Embed {
+    pub author: String,
+    pub icon_url: String,
+    pub colour: u32,
+}
+
+#[derive(Default, Serialize)]
+pub struct DeliveryMetrics {
+    pub sent: AtomicU64,
+    pub failed: AtomicU64,
+    pub dropped: AtomicU64,
+    pub coalesced: AtomicU64,
+}
+
+impl Outbox {
+    pub fn get() -> &'static Mutex<Self> {
+        static OUTBOX: OnceLock<Mutex<Outbox>> = OnceLock::new();
+
+        OUTBOX.get_or_init(|| Mutex::new(Self::init()))
+    }
+
+    pub fn metrics() -> &'static DeliveryMetrics {
+        static METRICS: OnceLock<DeliveryMetrics> = OnceLock::new();
+
+        METRICS.get_or_init(DeliveryMetrics::default)
+    }
+
+    pub fn init() -> Self {
+        let path =
+            PathBuf::from(env::var("CONFIG").expect("missing ENV `CONFIG`")).join("queue.json");
+
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            // kept for inspection instead of being overwritten by the next save
+            let aside =
+                path.with_extension(format!("json.corrupt-{}", chrono::Utc::now().timestamp()));
+            let moved = fs::rename(&path, &aside);
+            tracing::error!(
+                error = %e,
+                moved_to = %aside.display(),
+                moved = moved.is_ok(),
+                "Failed to read queue.json, starting with an empty queue"
+            );
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let path = PathBuf::from(env::var("CONFIG").expect("missing ENV `CONFIG`"));
+
+        fs::create_dir_all(&path).unwrap();
+
+        write_atomic(
+            &path.join("queue.json"),
+            &serde_json::to_vec_pretty(self).unwrap(),
+        )
+        .unwrap();
+    }
+
+    /// Queues a message, cancelling out an undelivered offline message if the user resumed.
+    ///
+    /// Neither message of a cancelled pair is sent, as the user never really went offline.
+    pub fn push(&mut self, outgoing: Outgoing) {
+        if outgoing.kind == "resumed"
+            && let Some(index) = self.queue.iter().rposition(|queued| {
+                queued.channel == outgoing.channel
+                    && queued.user == outgoing.user
+                    && queued.kind == "offline"
+            })
+        {
+            self.queue.remove(index);
+            Self::metrics().coalesced.fetch_add(2, Ordering::Relaxed);
+        } else {
+            self.queue.push(outgoing);
+        }
+
+        self.save();
+    }
+
+    /// Takes the first message of each channel that is due and not rate limited.
+    fn take_due(&mut self, now: u64) -> Vec<Outgoing> {
+        let rate_limit = Config::get().discord_rate_limit;
+        let mut seen = HashSet::new();
+        let mut due = Vec::new();
+        let mut index = 0;
+
+        while index < self.queue.len() {
+            let outgoing = &self.queue[index];
+
+            if !seen.insert(outgoing.channel)
+                || outgoing.next_attempt > now
+                || self
+                    .last_sent
+                    .get(&outgoing.channel)
+                    .is_some_and(|last| now < last + rate_limit)
+            {
+                index += 1;
+                continue;
+            }
+
+            self.last_sent.insert(outgoing.channel, now);
+            due.push(self.queue.remove(index));
+        }
+
+        due
+    }
+
+    /// Delivers queued messages forever, retrying failures with exponential backoff.
+    pub async fn deliver(http: &Http) {
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            let due = {
+                let mut outbox = Self::get().lock().await;
+                let due = outbox.take_due(now);
+                outbox.sending = due.len();
+                due
+            };
+            if due.is_empty() {
+                continue;
+            }
+
+            let mut retry = Vec::new();
+            for mut outgoing in due {
+                let Err(e) = outgoing.send(http).await else {
+                    Self::metrics().sent.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                };
+
+                Self::metrics().failed.fetch_add(1, Ordering::Relaxed);
+                outgoing.attempts += 1;
+
+                // missing access, unknown channel or an invalid message never succeed on retry
+                if discord_error(&e)
+                    .is_some_and(|(status, _)| (400..500).contains(&status) && status != 429)
+                {
+                    Self::metrics().dropped.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        channel = outgoing.channel,
+                        error = %e,
+                        "Dropped message rejected by discord"
+                    );
+                    continue;
+                }
+
+                if outgoing.attempts >= MAX_ATTEMPTS {
+                    Self::metrics().dropped.fetch_add(1, Ordering::Relaxed);
+                    tracing::error!(
+                        channel = outgoing.channel,
+                        attempts = outgoing.attempts,
+                        error = %e,
+                        "Dropped message"
+                    );
+                    continue;
+                }
+
+                tracing::warn!(
+                    channel = outgoing.channel,
+                    attempts = outgoing.attempts,
+                    error = %e,
+                    "Failed to send message"
+                );
+                outgoing.next_attempt = now + (1000 * 2u64.pow(outgoing.attempts)).min(MAX_BACKOFF);
+                retry.push(outgoing);
+            }
+
+            let mut outbox = Self::get().lock().await;
+            // failed messages go back to the front to keep each channel in order
+            outbox.queue.splice(0..0, retry);
+            outbox.sending = 0;
+            outbox.save();
+        }
+    }
+
+    /// Waits for `deliver` to send every message that is due, for at most `timeout`.
+    ///
+    /// Messages backing off after a failure stay queued for the next start.
+    pub async fn flush(timeout: Duration) {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        while tokio::time::Instant::now() < deadline {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            let outbox = Self::get().lock().await;
+            if outbox.sending == 0
+                && !outbox
+                    .queue
+                    .iter()
+                    .any(|outgoing| outgoing.next_attempt <= now)
+            {
+                break;
+            }
+            drop(outbox);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Self::get().lock().await.save();
+    }
+}
+
+impl Outgoing {
+    async fn send(&self, http: &Http) -> serenity::Result<()> {
+        let channel = if self.direct {
+            UserId::new(self.channel).create_dm_channel(http).await?.id
+        } else {
+            ChannelId::new(self.channel)
+        };
+
+        match &self.embed {
+            Some(embed) => {
+                channel
+                    .send_message(
+                        http,
+                        CreateMessage::new().embed(
+                            CreateEmbed::new()
+                                .author(
+                                    CreateEmbedAuthor::new(&embed.author).icon_url(&embed.icon_url),
+                                )
+                                .description(&self.content)
+                                .colour(embed.colour)
+                                .timestamp(
+                                    Timestamp::from_millis(self.created as i64)
+                                        .unwrap_or_else(|_| Timestamp::now()),
+                                ),
+                        ),
+                    )
+                    .await?
+            }
+            None => channel.say(http, &self.content).await?,
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use askama::Template;
+
+use crate::{Config, Record, Storage, Visibility};
+
+pub struct Overlap {
+    names: Vec<String>,
+    // seconds spent online together, indexed the same as `names`
+    matrix: Vec<Vec<u64>>,
+}
+
+#[derive(Template)]
+#[template(path = "overlap.html")]
+struct OverlapPage {
+    days: u64,
+    overlap: Overlap,
+    // longest overlap between two users, for shading
+    max: u64,
+}
+
+impl Overlap {
+    /// Overlap between every pair of public users since `from` (unix millis).
+    pub fn build(from: u64) -> Self {
+        let storage = Storage::copy();
+        let names = Config::get()
+            .users
+            .iter()
+            .filter(|entry| entry.visibility == Visibility::Public)
+            .map(|entry| entry.name.clone())
+            .filter(|name| storage.users.contains_key(name))
+            .collect::<Vec<_>>();
+
+        let matrix = names
+            .iter()
+            .map(|a| {
+                names
+                    .iter()
+                    .map(|b| {
+                        if a == b {
+                            0
+                        } else {
+                            Self::between(&storage.users[a], &storage.users[b], from)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self { names, matrix }
+    }
+
+    /// Total seconds both users were online since `from` (unix millis).
+    pub fn between(a: &[Record], b: &[Record], from: u64) -> u64 {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let interval = |record: &Record| {
+            (
+                record.beginning.max(from),
+                record.end.unwrap_or(now).max(from),
+            )
+        };
+
+        let (mut i, mut j) = (0, 0);
+        let mut total = 0;
+
+        while i < a.len() && j < b.len() {
+            let (a_start, a_end) = interval(&a[i]);
+            let (b_start, b_end) = interval(&b[j]);
+
+            total += a_end.min(b_end).saturating_sub(a_start.max(b_start));
+
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+
+        total / 1000
+    }
+
+    /// Public users sorted by time spent online together with `username` since `from` (unix
+    /// millis), most first. Only computes the row of `username` rather than the whole matrix.
+    pub fn online_with(username: &str, from: u64) -> Vec<(String, u64)> {
+        let storage = Storage::copy();
+        let public = |name: &str| {
+            Config::get()
+                .users
+                .iter()
+                .any(|entry| entry.name == name && entry.visibility == Visibility::Public)
+        };
+        let Some(records) = storage.users.get(username).filter(|_| public(username)) else {
+            return Vec::new();
+        };
+
+        let mut others = Config::get()
+            .users
+            .iter()
+            .filter(|entry| entry.name != username && entry.visibility == Visibility::Public)
+            .filter_map(|entry| {
+                let overlap = Self::between(records, storage.users.get(&entry.name)?, from);
+                (overlap > 0).then(|| (entry.name.clone(), overlap))
+            })
+            .collect::<Vec<_>>();
+        others.sort_by_key(|(_, overlap)| std::cmp::Reverse(*overlap));
+        others
+    }
+
+    pub fn html(days: u64) -> String {
+        // `days` comes from the query string
+        let from = (chrono::Utc::now().timestamp() as u64)
+            .saturating_sub(days.saturating_mul(86_400))
+            * 1000;
+        let overlap = Self::build(from);
+
+        OverlapPage {
+            days,
+            max: overlap
+                .matrix
+                .iter()
+                .flatten()
+                .max()
+                .copied()
+                .unwrap_or(0)
+                .max(1),
+            overlap,
+        }
+        .render()
+        .unwrap()
+    }
+}
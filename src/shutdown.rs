@@ -0,0 +1,62 @@
+use std::sync::OnceLock;
+
+use tokio::sync::watch;
+
+/// How far the process is through shutting down, in order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Phase {
+    Running,
+    // a signal was received, no new fetches are started
+    Stopping,
+    // the last fetch finished and storage is saved, no more events will be broadcast
+    Collected,
+}
+
+fn phase() -> &'static watch::Sender<Phase> {
+    static PHASE: OnceLock<watch::Sender<Phase>> = OnceLock::new();
+
+    PHASE.get_or_init(|| watch::channel(Phase::Running).0)
+}
+
+/// Moves shutdown forward to `next`, never backwards.
+pub fn advance(next: Phase) {
+    phase().send_if_modified(|phase| {
+        let modified = *phase < next;
+        *phase = (*phase).max(next);
+        modified
+    });
+}
+
+/// Resolves once shutdown has reached `target`.
+pub async fn reached(target: Phase) {
+    let _ = phase().subscribe().wait_for(|phase| *phase >= target).await;
+}
+
+/// Starts shutting down on the first SIGINT or SIGTERM, exits right away on the second.
+pub async fn listen_for_signals() {
+    #[cfg(unix)]
+    let mut terminate = {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut signal = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        async move || {
+            signal.recv().await;
+        }
+    };
+    // there is no SIGTERM elsewhere, only ctrl-c is listened for
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>;
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate() => tracing::info!("Received SIGTERM, shutting down"),
+    }
+    advance(Phase::Stopping);
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate() => {}
+    }
+    tracing::warn!("Received a second signal, exiting without finishing shutdown");
+    std::process::exit(1);
+}
@@ -1,26 +1,52 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
     error::Error,
     fs,
-    io::Write,
-    path::PathBuf,
-    sync::{Arc, OnceLock},
-    time::Duration,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant},
 };
 
+use chrono::{DateTime, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::{sync::Mutex, task::JoinSet};
+use tracing::Instrument;
 
-use crate::{Config, Mermaid, SENDER};
+use crate::{Config, Mermaid, Metrics, key_hint};
 
-pub static mut STORAGE_COPY: OnceLock<Storage> = OnceLock::new();
+// replaced by `Storage::publish`, readers keep the copy they got alive until they are done
+static STORAGE_COPY: RwLock<Option<Arc<Storage>>> = RwLock::new(None);
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Storage {
     pub last_fetch: u64,
     pub first_fetch: u64,
     pub users: HashMap<String, Vec<Record>>,
+    // most recent game type of each user
+    #[serde(default)]
+    pub modes: HashMap<String, String>,
+    // channel id to the id of its pinned status message
+    #[serde(default)]
+    pub status_messages: HashMap<u64, u64>,
+    // limit alerts already sent to each user, see `Storage::check_limits`
+    #[serde(default)]
+    pub alerts: HashMap<String, HashSet<String>>,
+    // username to discord users subscribed to their events by direct message
+    #[serde(default)]
+    pub subscriptions: HashMap<String, HashSet<u64>>,
+    // share token to the user whose page it grants access to
+    #[serde(default)]
+    pub shares: HashMap<String, String>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    All,
 }
 
 #[derive(Deserialize)]
@@ -29,10 +55,32 @@ pub struct Fetched {
     pub last_login: u64,
     #[serde(rename = "lastLogout")]
     pub last_logout: u64,
+    #[serde(rename = "mostRecentGameType")]
+    pub mode: Option<String>,
+}
+
+/// Replaces the file at `path` with `bytes`.
+///
+/// Written to a temporary file next to it first, so an interrupted write leaves the old file
+/// intact.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    fs::write(&temporary, bytes)?;
+    fs::rename(temporary, path)
 }
 
 impl Storage {
-    pub fn print_log(user: &str) -> String {
+    /// Login and logout times of `user`, most recent first.
+    pub fn login_records(user: &str) -> Vec<(String, String)> {
+        let format = |time: u64| {
+            let s = chrono::DateTime::from_timestamp_millis(time as i64)
+                .unwrap()
+                .to_rfc2822();
+            s[..s.len() - 5].to_string()
+        };
+
         Self::copy()
             .users
             .get(user)
@@ -40,33 +88,52 @@ impl Storage {
             .iter()
             .rev()
             .map(|record| {
-                format!(
-                    r#"  <tr>
-    <td>{}</td>
-    <td>{}</td>
-  </tr>"#,
-                    {
-                        let s = chrono::DateTime::from_timestamp_millis(record.beginning as i64)
-                            .unwrap()
-                            .to_rfc2822();
-                        s[..s.len() - 5].to_string()
-                    },
-                    if let Some(end) = record.end {
-                        let s = chrono::DateTime::from_timestamp_millis(end as i64)
-                            .unwrap()
-                            .to_rfc2822();
-                        s[..s.len() - 5].to_string()
-                    } else {
-                        "online".to_string()
-                    }
+                (
+                    format(record.beginning),
+                    record.end.map(format).unwrap_or("online".to_string()),
                 )
             })
-            .collect::<Vec<_>>()
-            .join("\n")
+            .collect()
+    }
+
+    /// Seconds `user` spent online since `from` (unix millis).
+    pub fn playtime(&self, user: &str, from: u64) -> u64 {
+        self.playtime_between(user, from, u64::MAX)
+    }
+
+    /// Seconds `user` spent online between `from` and `to` (unix millis).
+    pub fn playtime_between(&self, user: &str, from: u64, to: u64) -> u64 {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        self.users
+            .get(user)
+            .map(|records| {
+                records
+                    .iter()
+                    .map(|record| {
+                        record
+                            .end
+                            .unwrap_or(now)
+                            .min(to)
+                            .saturating_sub(record.beginning.max(from))
+                    })
+                    .sum::<u64>()
+                    / 1000
+            })
+            .unwrap_or(0)
+    }
+
+    /// Replaces the copy read by the website with a clone of `self`.
+    pub fn publish(&self) {
+        let cloned = Arc::new(self.clone());
+        *STORAGE_COPY.write().unwrap() = Some(cloned);
     }
 
-    pub fn copy() -> &'static Self {
-        unsafe { STORAGE_COPY.get().unwrap() }
+    pub fn copy() -> Arc<Self> {
+        STORAGE_COPY
+            .read()
+            .unwrap()
+            .clone()
+            .expect("storage is published before the first fetch")
     }
 
     pub fn is_online(user: &str) -> bool {
@@ -81,14 +148,11 @@ impl Storage {
 
         fs::create_dir_all(&path).unwrap();
 
-        fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path.join("storage.json"))
-            .unwrap()
-            .write_all(serde_json::to_vec_pretty(self).unwrap().as_slice())
-            .unwrap();
+        write_atomic(
+            &path.join("storage.json"),
+            &serde_json::to_vec_pretty(self).unwrap(),
+        )
+        .unwrap();
     }
 
     pub fn expire(&mut self) {
@@ -136,6 +200,16 @@ impl Storage {
         serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
     }
 
+    /// Reads `storage.json` without creating it, for commands that only look at the records.
+    pub fn read() -> Result<Storage, String> {
+        let path =
+            PathBuf::from(env::var("CONFIG").expect("missing ENV `CONFIG`")).join("storage.json");
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read `{}` - {e}", path.display()))?;
+        serde_json::from_str(&content).map_err(|e| format!("invalid `{}` - {e}", path.display()))
+    }
+
     pub fn push_beginning(&mut self, username: String, time: u64) {
         let user = self.users.entry(username.clone()).or_default();
 
@@ -158,14 +232,11 @@ impl Storage {
             {
                 user.last_mut().unwrap().end = None;
                 user.last_mut().unwrap().session_begin = time;
-                SENDER
-                    .get()
-                    .unwrap()
-                    .send(crate::BroadcastEvent::Online {
-                        user: username,
-                        resumed: true,
-                    })
-                    .unwrap();
+                crate::BroadcastEvent::Online {
+                    user: username,
+                    resumed: true,
+                }
+                .broadcast();
                 return;
             }
             _ => user.push(Record {
@@ -175,14 +246,11 @@ impl Storage {
             }),
         }
 
-        SENDER
-            .get()
-            .unwrap()
-            .send(crate::BroadcastEvent::Online {
-                user: username,
-                resumed: false,
-            })
-            .unwrap();
+        crate::BroadcastEvent::Online {
+            user: username,
+            resumed: false,
+        }
+        .broadcast();
     }
 
     pub fn push_logout(&mut self, username: String, time: u64, login: u64) {
@@ -202,21 +270,18 @@ impl Storage {
             _ => user.last_mut().unwrap().end = Some(time),
         }
 
-        SENDER
-            .get()
-            .unwrap()
-            .send(crate::BroadcastEvent::Offline {
-                user: username,
-                duration: Duration::from_millis(
-                    (chrono::DateTime::from_timestamp_millis(time as i64).unwrap()
-                        - chrono::DateTime::from_timestamp_millis(
-                            user.last().unwrap().beginning as i64,
-                        )
-                        .unwrap())
-                    .num_milliseconds() as u64,
-                ),
-            })
-            .unwrap();
+        crate::BroadcastEvent::Offline {
+            user: username,
+            duration: Duration::from_millis(
+                (chrono::DateTime::from_timestamp_millis(time as i64).unwrap()
+                    - chrono::DateTime::from_timestamp_millis(
+                        user.last().unwrap().beginning as i64,
+                    )
+                    .unwrap())
+                .num_milliseconds() as u64,
+            ),
+        }
+        .broadcast();
     }
 
     pub async fn fetch_one(uuid: &str, key: &str) -> Result<Fetched, Box<dyn Error>> {
@@ -228,44 +293,100 @@ impl Storage {
             &reqwest::get(format!(
                 "https://api.hypixel.net/v2/player?uuid={uuid}&key={key}"
             ))
-            .await?
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            // the url contains the api key
+            .map_err(reqwest::Error::without_url)?
             .text()
             .await?,
         )?
         .player)
     }
 
+    #[tracing::instrument(name = "fetch", skip_all)]
     pub async fn fetch(&mut self, keys: &Arc<Vec<&'static String>>, index: &mut usize) {
         let config = Config::get();
+        let started = Instant::now();
 
         let mut set = JoinSet::new();
 
+        let previous_fetch = self.last_fetch;
         self.last_fetch = chrono::Utc::now().timestamp() as u64;
 
         config.users.iter().for_each(|user| {
             let user = user.clone();
             let this_index = *index;
             let keys_copy = keys.clone();
-            set.spawn(async move {
-                match Self::fetch_one(&user.uuid, keys_copy[this_index]).await {
-                    Ok(fetched) => return Some((user.name, fetched)),
-                    Err(e) => println!(
-                        "Failed to fetch user={}, uuid={}, key={} - {e}",
-                        user.uuid, user.uuid, keys_copy[this_index]
-                    ),
-                }
+            let span = tracing::info_span!("user", user = %user.name);
+            set.spawn(
+                async move {
+                    let key = keys_copy[this_index];
+                    Metrics::get().request(key);
+                    match Self::fetch_one(&user.uuid, key).await {
+                        Ok(fetched) => {
+                            tracing::debug!(
+                                last_login = fetched.last_login,
+                                last_logout = fetched.last_logout,
+                                "Fetched"
+                            );
+                            return Some((user.name, fetched));
+                        }
+                        Err(e) => {
+                            Metrics::get().error(e.as_ref());
+                            tracing::warn!(
+                                uuid = %user.uuid,
+                                key = %key_hint(key),
+                                error = %e,
+                                "Failed to fetch"
+                            );
+                        }
+                    }
 
-                None
-            });
+                    None
+                }
+                .instrument(span),
+            );
 
             *index = (*index + 1) % keys.len();
         });
 
-        for (name, fetched) in set.join_all().await.into_iter().flatten() {
+        let fetched = set
+            .join_all()
+            .await
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        Metrics::get().fetched(started.elapsed());
+        tracing::info!(
+            fetched = fetched.len(),
+            failed = config.users.len() - fetched.len(),
+            took_ms = started.elapsed().as_millis() as u64,
+            "Fetched users"
+        );
+
+        for (name, fetched) in fetched {
+            Metrics::get().success(&name, self.last_fetch);
+            if let Some(mode) = fetched.mode {
+                self.modes.insert(name.clone(), mode);
+            }
             self.push_beginning(name.clone(), fetched.last_login);
             self.push_logout(name.clone(), fetched.last_logout, fetched.last_login);
         }
 
+        if previous_fetch != 0 {
+            for user in config.users.iter() {
+                Metrics::get().played(
+                    &user.name,
+                    self.playtime_between(
+                        &user.name,
+                        previous_fetch * 1000,
+                        self.last_fetch * 1000,
+                    ),
+                );
+            }
+        }
+
+        self.check_limits();
         self.expire();
         self.first_fetch = self
             .first_fetch
@@ -282,3 +403,68 @@ pub struct Record {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end: Option<u64>,
 }
+
+impl Period {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "day" => Some(Self::Day),
+            "week" => Some(Self::Week),
+            "month" => Some(Self::Month),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+            Self::All => "all",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Day => "the last 24 hours",
+            Self::Week => "the last 7 days",
+            Self::Month => "the last 30 days",
+            Self::All => "all time",
+        }
+    }
+
+    /// Start of the period in unix millis.
+    pub fn from(&self) -> u64 {
+        let days = match self {
+            Self::Day => 1,
+            Self::Week => 7,
+            Self::Month => 30,
+            Self::All => return 0,
+        };
+        (chrono::Utc::now().timestamp() as u64).saturating_sub(days * 3600 * 24) * 1000
+    }
+}
+
+impl Record {
+    /// Splits the record at midnight (UTC), an ongoing record is cut off at `now`.
+    pub fn split_days(&self, now: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut start = DateTime::from_timestamp_millis(self.beginning as i64).unwrap();
+        let end = DateTime::from_timestamp_millis(
+            self.end.unwrap_or(now.timestamp_millis() as u64) as i64,
+        )
+        .unwrap();
+        let mut segments = Vec::new();
+
+        while start.date_naive() != end.date_naive() && start < end {
+            let midnight = start.with_time(NaiveTime::MIN).unwrap() + chrono::Duration::days(1);
+            segments.push((start, midnight));
+            start = midnight;
+        }
+
+        if start < end {
+            segments.push((start, end));
+        }
+
+        segments
+    }
+}
@@ -1,24 +1,27 @@
 use std::{sync::Arc, time::Duration};
 
-use crate::{Config, STORAGE_COPY, Storage};
+use crate::{Config, Phase, Storage, shutdown};
 
 pub async fn fetch() {
-    let config = Config::get();
-    let mut key_index = (chrono::Utc::now().timestamp_micros() % config.keys.len() as i64) as usize;
-    let keys = Arc::new(config.keys.iter().collect::<Vec<_>>());
+    let mut key_index = chrono::Utc::now().timestamp_micros() as usize;
 
     loop {
-        let cloned = Storage::get().lock().await.clone();
-        unsafe {
-            if STORAGE_COPY.get().is_some() {
-                *STORAGE_COPY.get_mut().unwrap() = cloned;
-            } else {
-                let _ = STORAGE_COPY.set(cloned);
-            }
-        }
+        Storage::get().lock().await.publish();
         let now = chrono::Utc::now().timestamp() as u64;
-        let sleep = (Storage::get().lock().await.last_fetch + config.interval).saturating_sub(now);
-        tokio::time::sleep(Duration::from_secs(sleep)).await;
+        let sleep =
+            (Storage::get().lock().await.last_fetch + Config::get().interval).saturating_sub(now);
+        // a fetch in progress is always finished before stopping
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(sleep)) => {}
+            _ = shutdown::reached(Phase::Stopping) => break,
+        }
+        // keys can be changed from the admin page between fetches
+        let keys = Arc::new(Config::get().keys.iter().collect::<Vec<_>>());
+        key_index %= keys.len();
         Storage::fetch(&mut *Storage::get().lock().await, &keys, &mut key_index).await;
     }
+
+    Storage::get().lock().await.save();
+    tracing::info!("Fetching stopped, storage saved");
+    shutdown::advance(Phase::Collected);
 }
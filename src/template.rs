@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, Days, NaiveTime};
+
+use crate::{BroadcastEvent, Config, LimitKind, Storage, format_duration};
+
+/// Values substituted for `{name}` in broadcast templates.
+pub struct Placeholders(HashMap<&'static str, String>);
+
+impl Placeholders {
+    pub fn new(event: &BroadcastEvent, storage: &Storage) -> Self {
+        let now = chrono::Utc::now();
+        let user = event.user();
+        let record = storage.users.get(user).and_then(|records| records.last());
+        let beginning = record.map(|record| record.beginning).unwrap_or(0);
+
+        let duration = match event {
+            BroadcastEvent::Offline { duration, .. } => duration.as_secs(),
+            _ => (now.timestamp_millis() as u64).saturating_sub(beginning) / 1000,
+        };
+        let midnight = now.with_time(NaiveTime::MIN).unwrap();
+        let monday = midnight - Days::new(now.weekday().num_days_from_monday() as u64);
+
+        let mut placeholders = HashMap::from([
+            ("user", user.to_string()),
+            ("duration", format_duration(duration)),
+            (
+                "start",
+                chrono::DateTime::from_timestamp_millis(beginning as i64)
+                    .unwrap()
+                    .format("%H:%M")
+                    .to_string(),
+            ),
+            ("start_unix", (beginning / 1000).to_string()),
+            (
+                "today",
+                format_duration(storage.playtime(user, midnight.timestamp_millis() as u64)),
+            ),
+            (
+                "week",
+                format_duration(storage.playtime(user, monday.timestamp_millis() as u64)),
+            ),
+            ("mode", storage.modes.get(user).cloned().unwrap_or_default()),
+            ("streak", Self::streak(storage, user).to_string()),
+            (
+                "avatar",
+                Config::get()
+                    .users
+                    .iter()
+                    .find(|entry| entry.name == user)
+                    .map(|entry| format!("https://mc-heads.net/avatar/{}", entry.uuid))
+                    .unwrap_or_default(),
+            ),
+        ]);
+
+        if let BroadcastEvent::Limit {
+            kind,
+            exceeded,
+            used,
+            max,
+            ..
+        } = event
+        {
+            placeholders.insert("limit", kind.name().to_string());
+            placeholders.insert(
+                "status",
+                if *exceeded {
+                    "has exceeded"
+                } else {
+                    "is approaching"
+                }
+                .to_string(),
+            );
+
+            // the curfew is reported as the time of day against the allowed window
+            match Config::get().limits.get(user).and_then(|limits| {
+                matches!(kind, LimitKind::Curfew).then_some(limits.allowed.as_ref()?)
+            }) {
+                Some(allowed) => {
+                    placeholders.insert("used", now.format("%H:%M").to_string());
+                    placeholders.insert("max", format!("{}-{}", allowed.from, allowed.to));
+                }
+                None => {
+                    placeholders.insert("used", format_duration(*used));
+                    placeholders.insert("max", format_duration(*max));
+                }
+            }
+        }
+
+        Self(placeholders)
+    }
+
+    /// Number of consecutive days up to today the user has been online.
+    fn streak(storage: &Storage, user: &str) -> u64 {
+        let now = chrono::Utc::now();
+        let mut days = storage
+            .users
+            .get(user)
+            .map(|records| {
+                records
+                    .iter()
+                    .flat_map(|record| record.split_days(now))
+                    .map(|(start, _)| start.date_naive())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        days.dedup();
+
+        days.iter()
+            .rev()
+            .zip(now.date_naive().iter_days().rev())
+            .take_while(|(day, expected)| **day == *expected)
+            .count() as u64
+    }
+
+    pub fn get(&self, name: &str) -> &str {
+        self.0.get(name).map(String::as_str).unwrap_or("")
+    }
+
+    /// Replaces every known `{name}` in `template`, unknown names are left as is.
+    pub fn render(&self, template: &str) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            rest = &rest[open..];
+
+            match rest
+                .find('}')
+                .and_then(|close| Some((close, self.0.get(&rest[1..close])?)))
+            {
+                Some((close, value)) => {
+                    rendered.push_str(value);
+                    rest = &rest[close + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        rendered.push_str(rest);
+        rendered
+    }
+}
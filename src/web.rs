@@ -1,29 +1,91 @@
-use std::{cmp::Ordering, fs};
+use std::{cmp::Ordering, convert::Infallible, sync::OnceLock};
 
-use axum::{Router, extract::Path, response::Html, routing::get};
+use askama::Template;
+use axum::{
+    Router,
+    extract::{Path, Query},
+    http::{StatusCode, header},
+    response::{
+        Html, Json,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
+};
+use futures::{Stream, stream};
+use serde::Deserialize;
+use tokio::sync::broadcast;
 
-use crate::{Config, Storage, mermaid::Mermaid};
+use crate::{
+    Access, BroadcastEvent, Compare, Config, ExportFilter, Health, Metrics, Outbox, Overlap, Phase,
+    Record, Storage, admin, main_css, mermaid::Mermaid, serve_asset, shutdown,
+};
 
-pub const HEART: &str = "<svg style=\"transform: translateY(3px); height: 1em;\" class=\"heart\" viewBox=\"0 0 16 16\" fill=\"#a6e3a1\" xmlns=\"http://www.w3.org/2000/svg\"><g id=\"SVGRepo_bgCarrier\" stroke-width=\"0\"></g><g id=\"SVGRepo_tracerCarrier\" stroke-linecap=\"round\" stroke-linejoin=\"round\"></g><g id=\"SVGRepo_iconCarrier\"> <path d=\"M1.24264 8.24264L8 15L14.7574 8.24264C15.553 7.44699 16 6.36786 16 5.24264V5.05234C16 2.8143 14.1857 1 11.9477 1C10.7166 1 9.55233 1.55959 8.78331 2.52086L8 3.5L7.21669 2.52086C6.44767 1.55959 5.28338 1 4.05234 1C1.8143 1 0 2.8143 0 5.05234V5.24264C0 6.36786 0.44699 7.44699 1.24264 8.24264Z\" fill=\"#a6e3a1\"></path> </g></svg>";
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexPage {
+    // name and whether they are online, online users first
+    users: Vec<(String, bool)>,
+    compare: String,
+}
+
+#[derive(Template)]
+#[template(path = "not_found.html")]
+struct NotFound<'a> {
+    message: &'a str,
+}
+
+pub fn events() -> &'static broadcast::Sender<BroadcastEvent> {
+    static EVENTS: OnceLock<broadcast::Sender<BroadcastEvent>> = OnceLock::new();
+
+    EVENTS.get_or_init(|| broadcast::channel(64).0)
+}
 
 pub async fn run() {
     let app = Router::new()
         .route("/", get(root))
+        .route("/compare", get(compare))
+        .route("/overlap", get(overlap))
+        .route("/events", get(sse))
+        .route("/api/stats/{user}", get(stats))
+        .route("/api/delivery", get(delivery))
+        .route("/api/share/{user}", post(share).delete(unshare))
+        .route("/export/csv", get(export_csv))
+        .route("/export/jsonl", get(export_jsonl))
+        .route("/export/ics/{user}", get(export_ics))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/admin", get(admin::page))
+        .route("/admin/login", post(admin::log_in))
+        .route("/admin/logout", post(admin::log_out))
+        .route("/admin/users", post(admin::add_user))
+        .route("/admin/users/{name}", post(admin::update_user))
+        .route("/admin/users/{name}/delete", post(admin::remove_user))
+        .route("/admin/channels", post(admin::add_channel))
+        .route("/admin/channels/{id}", post(admin::update_channel))
+        .route("/admin/channels/{id}/delete", post(admin::remove_channel))
+        .route("/admin/keys", post(admin::add_key))
+        .route("/admin/keys/delete", post(admin::remove_key))
+        .route("/readyz", get(readyz))
         .route("/{user}", get(user))
-        .route("/main.css", get(css));
+        .route("/static/{*path}", get(serve_asset))
+        .route("/main.css", get(main_css))
+        .fallback(async || not_found("No such page"));
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", Config::get().port))
         .await
         .unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::reached(Phase::Stopping))
+        .await
+        .unwrap();
 }
 
-async fn root() -> Html<String> {
+async fn root(access: Access) -> Html<String> {
     let mut users = Config::get()
         .users
         .iter()
         .map(|entry| entry.name.clone())
-        .filter(|name| Storage::copy().users.contains_key(name))
+        .filter(|name| Storage::copy().users.contains_key(name) && access.can_list(name))
         .collect::<Vec<_>>();
     users.sort_by(
         |a, b| match (Storage::is_online(a), Storage::is_online(b)) {
@@ -32,115 +94,257 @@ async fn root() -> Html<String> {
             _ => a.cmp(b),
         },
     );
-    Html(format!(
-        r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-  <meta charset="utf-8">
-  <title>Hypixel Screentime</title>
-</head>
-<body>
-<style>
-body {{
-    margin: 0px;
-    background: #333333;
-    text-align: center;
-    color: #eceff4 !important;
-    font-family: Arial, Helvetica, sans-serif;
-}}
-
-.name {{
-    padding: 8px;
-    font-size: larger;
-}}
-
-.name a {{
-    text-decoration: none;
-}}
-
-.name a:hover {{
-    transition: 100ms;
-    color: #ee99a0 !important;
-}}
-
-.name a {{
-    color: #eceff4 !important;
-    transition: 100ms;
-}}
-
-.name a.green, .green {{
-    color: #a6e3a1 !important;
-}}
-
-#container {{
-display:flex;
-}}
-
-#info {{
-  flex:1;
-  text-align: left;
-  display: inline-block;
-  align-content: center;
-  margin-right: 100px;
-}}
-
-#players {{
-  text-align: center;
-}}
-</style>
-
-<div style="height: 100vh; display: flex; margin: 0px;">
-<div style="flex: 1; align-content: center; display: inline-block;">
-
-<!-- centering begin -->
-<div style="margin: auto; display: inline-block; ">
-<div id="container">
-<div id="info" style="display: inline-block; max-width: fit-content !important; font-size: 1.5em;">
-    <h1 style="text-shadow: 3px 3px #ce4008;">Hypixel Screentime</h1>
-</div>
-<div id="players" style="display: inline-block; max-width: fit-content; !important">
-    {}
-</div>
-</div>
-</div>
-
-<br>
-<br>
-<footer>
-  <p>Hypixel Screentime by <i><b>Sirius</b></i> | <span style="border-bottom: 2px solid #a6e3a1;"><a class="green" target="_blank" href="https://github.com/siriusmart/hypixel-screentime" style="text-decoration: none;">Written with {HEART} in Rust</a></span></p>
-</footer>
-<!-- centering end -->
-
-
-</div>
-</div>
-
-</body>
-</html>"#,
-        users
-            .iter()
-            .map(|name| format!(
-                r#"<div class="name"><a{} href="{name}">{name}{}</a></div>"#,
-                if Storage::is_online(name) {
-                    " class=\"green\""
-                } else {
-                    ""
-                },
-                if Storage::is_online(name) {
-                    " (online)"
-                } else {
-                    ""
-                }
-            ))
-            .collect::<Vec<_>>()
-            .join("\n")
+    Html(
+        IndexPage {
+            compare: users.join(","),
+            users: users
+                .into_iter()
+                .map(|name| {
+                    let online = Storage::is_online(&name);
+                    (name, online)
+                })
+                .collect(),
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+async fn user(access: Access, Path((user,)): Path<(String,)>) -> (StatusCode, Html<String>) {
+    if !access.can_view(&user) {
+        return not_found("No such user");
+    }
+
+    match Mermaid::html(&user) {
+        Some(page) => (StatusCode::OK, Html(page)),
+        None => not_found("No such user"),
+    }
+}
+
+pub fn not_found(message: &str) -> (StatusCode, Html<String>) {
+    (
+        StatusCode::NOT_FOUND,
+        Html(NotFound { message }.render().unwrap()),
+    )
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    users: String,
+}
+
+async fn compare(
+    access: Access,
+    Query(query): Query<CompareQuery>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    Compare::new(
+        &query
+            .users
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && access.can_view(name))
+            .collect::<Vec<_>>(),
+    )
+    .html()
+    .map(Html)
+    .ok_or_else(|| not_found("No such user"))
+}
+
+#[derive(Deserialize)]
+struct OverlapQuery {
+    days: Option<u64>,
+}
+
+async fn overlap(Query(query): Query<OverlapQuery>) -> Html<String> {
+    Html(Overlap::html(query.days.unwrap_or(Config::get().expire)))
+}
+
+async fn stats(
+    access: Access,
+    Path((user,)): Path<(String,)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let storage = Storage::copy();
+    let records = storage
+        .users
+        .get(&user)
+        .filter(|_| access.can_view(&user))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // the cached charts would wait on a fetch holding storage
+    let mermaid = Mermaid::build(records);
+    Ok(Json(serde_json::json!({
+        "user": user,
+        "online": Storage::is_online(&user),
+        "sessions": mermaid.sessions.stats(),
+        "session_histogram": mermaid.sessions.histogram(),
+        "timeofday": mermaid.timeofday.averages(),
+        "daily": mermaid.daily.averages(),
+        "weekly": mermaid.weekly.averages(),
+    })))
+}
+
+async fn delivery(access: Access) -> Result<Json<serde_json::Value>, StatusCode> {
+    access.manage()?;
+    Ok(Json(serde_json::json!({
+        "queued": Outbox::get().lock().await.queue.len(),
+        "metrics": Outbox::metrics(),
+    })))
+}
+
+async fn share(
+    access: Access,
+    Path((user,)): Path<(String,)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    access.admin()?;
+    if !Config::get().users.iter().any(|entry| entry.name == user) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let token = Storage::get().lock().await.share(&user);
+    Ok(Json(serde_json::json!({
+        "user": user,
+        "token": token,
+        // encoded like the links in the templates, names can contain spaces and `&`
+        "url": format!(
+            "/{}?token={token}",
+            askama::filters::urlencode_strict(&user).unwrap()
+        ),
+    })))
+}
+
+async fn unshare(
+    access: Access,
+    Path((user,)): Path<(String,)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    access.admin()?;
+    let revoked = Storage::get().lock().await.unshare(&user);
+    Ok(Json(
+        serde_json::json!({ "user": user, "revoked": revoked }),
+    ))
+}
+
+async fn export_csv(
+    access: Access,
+    Query(filter): Query<ExportFilter>,
+) -> Result<([(header::HeaderName, &'static str); 2], String), (StatusCode, String)> {
+    let storage = Storage::copy();
+    let sessions = exported(&access, &filter, &storage)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"sessions.csv\"",
+            ),
+        ],
+        crate::csv(&sessions),
+    ))
+}
+
+async fn export_jsonl(
+    access: Access,
+    Query(filter): Query<ExportFilter>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), (StatusCode, String)> {
+    let storage = Storage::copy();
+    let sessions = exported(&access, &filter, &storage)?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/jsonl; charset=utf-8")],
+        crate::jsonl(&sessions),
     ))
 }
 
-async fn user(Path((user,)): Path<(String,)>) -> Html<String> {
-    Html(Mermaid::html(&user))
+// sessions of the users `access` can list
+fn exported<'a>(
+    access: &Access,
+    filter: &ExportFilter,
+    storage: &'a Storage,
+) -> Result<Vec<(&'a str, &'a Record)>, (StatusCode, String)> {
+    access
+        .manage()
+        .map_err(|status| (status, "admin token required".to_string()))?;
+    let mut sessions = filter
+        .sessions(storage)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    sessions.retain(|(user, _)| access.can_list(user));
+    Ok(sessions)
+}
+
+async fn export_ics(
+    access: Access,
+    Path((user,)): Path<(String,)>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), StatusCode> {
+    if !access.can_view(&user) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let storage = Storage::copy();
+    let records = storage.users.get(&user).ok_or(StatusCode::NOT_FOUND)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        crate::ics(&user, records),
+    ))
 }
 
-async fn css() -> String {
-    fs::read_to_string("./main.css").unwrap()
+async fn metrics(
+    access: Access,
+) -> Result<([(header::HeaderName, &'static str); 1], String), StatusCode> {
+    access.manage()?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        Metrics::get().render(&access),
+    ))
+}
+
+async fn healthz() -> (StatusCode, Json<Health>) {
+    health(Health::live())
+}
+
+async fn readyz() -> (StatusCode, Json<Health>) {
+    health(Health::ready())
+}
+
+fn health(health: Health) -> (StatusCode, Json<Health>) {
+    let status = if health.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+async fn sse(access: Access) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(
+        (events().subscribe(), access),
+        |(mut rx, access)| async move {
+            loop {
+                // open streams would keep the server from shutting down
+                let received = tokio::select! {
+                    received = rx.recv() => received,
+                    _ = shutdown::reached(Phase::Stopping) => return None,
+                };
+                let data = match received {
+                    Ok(event) if !access.can_list(event.user()) => continue,
+                    Ok(BroadcastEvent::Online { user, resumed }) => serde_json::json!({
+                        "type": if resumed { "resumed" } else { "online" },
+                        "user": user,
+                        "online": true,
+                    }),
+                    Ok(BroadcastEvent::Offline { user, duration }) => serde_json::json!({
+                        "type": "offline",
+                        "user": user,
+                        "online": false,
+                        "duration": duration.as_secs(),
+                    }),
+                    Ok(BroadcastEvent::Limit { .. })
+                    | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+
+                return Some((Ok(Event::default().data(data.to_string())), (rx, access)));
+            }
+        },
+    );
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
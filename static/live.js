@@ -0,0 +1,8 @@
+// keeps elements with a `data-user` attribute in sync with `/events`
+new EventSource("/events" + location.search).onmessage = (message) => {
+  const event = JSON.parse(message.data);
+  document.querySelectorAll(`[data-user="${CSS.escape(event.user)}"]`).forEach((element) => {
+    element.classList.toggle("green", event.online);
+    element.textContent = event.user + (event.online ? " (online)" : "");
+  });
+};
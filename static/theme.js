@@ -0,0 +1,14 @@
+// loaded before the body so pages are drawn in the saved theme, or the system one if unset
+document.documentElement.dataset.theme =
+  localStorage.getItem("theme") ??
+  (matchMedia("(prefers-color-scheme: light)").matches ? "light" : "dark");
+
+function toggleTheme() {
+  const theme = document.documentElement.dataset.theme === "light" ? "dark" : "light";
+  localStorage.setItem("theme", theme);
+  document.documentElement.dataset.theme = theme;
+  // charts keep the theme they were drawn with
+  if (document.querySelector(".mermaid")) {
+    location.reload();
+  }
+}